@@ -1,45 +1,272 @@
+#[path = "src/shader_compiler.rs"]
+mod shader_compiler;
+
 use anyhow::{bail, Context, Result};
 use fs_extra::{self, file::read_to_string};
 use glob::glob;
-use shaderc::{self, Compiler, ShaderKind};
-use std::{fs::write, path::PathBuf};
+use naga::{
+    valid::ModuleInfo, Binding, ImageClass, ImageDimension, Module, ScalarKind, ShaderStage,
+    StorageAccess, StorageClass, TypeInner,
+};
+use serde::Deserialize;
+use shader_compiler::{compile, preprocess, ShaderKind};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Write as _,
+    fs::{create_dir_all, write},
+    path::{Path, PathBuf},
+};
+
+const SHADER_DIR: &str = "src";
+const MANIFEST: &str = "src/shaders.toml";
+
+#[derive(Deserialize, Default)]
+struct Manifest {
+    #[serde(default)]
+    variant: Vec<Variant>,
+}
+
+#[derive(Deserialize)]
+struct Variant {
+    shader: String,
+    name: String,
+    #[serde(default)]
+    defines: HashMap<String, String>,
+}
 
 struct ShaderData {
     src: String,
     src_path: PathBuf,
-    spv_path: PathBuf,
+    // path relative to SHADER_DIR, or `<variant>.<ext>` for variants
+    name: String,
     kind: ShaderKind,
+    includes: Vec<PathBuf>,
+    defines: HashMap<String, String>,
 }
 
 impl ShaderData {
     pub fn load(src_path: PathBuf) -> Result<Self> {
-        let extension = src_path
-            .extension()
-            .context("file has no extension")?
+        let kind = ShaderKind::from_path(&src_path)?;
+        let mut includes = Vec::new();
+        let src = preprocess(&src_path, Path::new(SHADER_DIR), &mut includes)?;
+        let name = src_path
+            .strip_prefix(SHADER_DIR)?
             .to_str()
-            .context("extension cannot be converted to &str")?;
-        let kind = match extension {
-            "vert" => ShaderKind::Vertex,
-            "frag" => ShaderKind::Fragment,
-            "comp" => ShaderKind::Compute,
-            _ => bail!("unsupported shader: {:?}", src_path),
-        };
-        let src = read_to_string(src_path.clone())?;
-        let spv_path = src_path.with_extension(format!("{}.spv", extension));
+            .context("path cannot be converted to &str")?
+            .replace('\\', "/");
         Ok(Self {
             src,
             src_path,
-            spv_path,
+            name,
             kind,
+            includes,
+            defines: HashMap::new(),
+        })
+    }
+
+    // same source compiled with the variant's defines, named `<name>.<ext>`
+    pub fn variant(&self, variant: &Variant) -> Self {
+        let extension = self.src_path.extension().unwrap().to_str().unwrap();
+        Self {
+            src: self.src.clone(),
+            src_path: self.src_path.clone(),
+            name: format!("{}.{}", variant.name, extension),
+            kind: self.kind,
+            includes: self.includes.clone(),
+            defines: variant.defines.clone(),
+        }
+    }
+
+    pub fn compile(&self) -> Result<(Vec<u8>, Module, ModuleInfo)> {
+        compile(&self.src, &self.src_path, self.kind, &self.defines)
+    }
+}
+
+fn vertex_format(inner: &TypeInner) -> Result<(String, u32)> {
+    let (kind, width, count) = match *inner {
+        TypeInner::Scalar { kind, width } => (kind, width, 1),
+        TypeInner::Vector { size, kind, width } => (kind, width, size as u32),
+        _ => bail!("unsupported vertex input type {:?}", inner),
+    };
+    let prefix = match (kind, width) {
+        (ScalarKind::Float, 4) => "Float32",
+        (ScalarKind::Sint, 4) => "Sint32",
+        (ScalarKind::Uint, 4) => "Uint32",
+        _ => bail!("unsupported vertex input type {:?}", inner),
+    };
+    let format = match count {
+        1 => prefix.to_string(),
+        _ => format!("{}x{}", prefix, count),
+    };
+    Ok((format, 4 * count))
+}
+
+// a single interleaved buffer holding every `location` input of the vertex entry point,
+// and the names of the inputs in the order of its attributes
+fn reflect_vertex_layout(module: &Module) -> Result<(String, String)> {
+    let none = || ("None".to_string(), "&[]".to_string());
+    let entry_point = match module
+        .entry_points
+        .iter()
+        .find(|entry_point| entry_point.stage == ShaderStage::Vertex)
+    {
+        Some(entry_point) => entry_point,
+        None => return Ok(none()),
+    };
+    let mut inputs = Vec::new();
+    for argument in &entry_point.function.arguments {
+        match (&argument.binding, &module.types[argument.ty].inner) {
+            (Some(Binding::Location { location, .. }), inner) => {
+                inputs.push((*location, &argument.name, inner))
+            }
+            (None, TypeInner::Struct { members, .. }) => {
+                for member in members {
+                    if let Some(Binding::Location { location, .. }) = member.binding {
+                        inputs.push((location, &member.name, &module.types[member.ty].inner));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if inputs.is_empty() {
+        return Ok(none());
+    }
+    inputs.sort_by_key(|(location, _, _)| *location);
+    let mut attributes = String::new();
+    let mut names = String::new();
+    let mut offset = 0;
+    for (location, name, inner) in inputs {
+        write!(names, "{:?}, ", name.as_deref().unwrap_or_default())?;
+        let (format, size) = vertex_format(inner)?;
+        write!(
+            attributes,
+            "wgpu::VertexAttribute {{ format: wgpu::VertexFormat::{}, offset: {}, shader_location: {} }}, ",
+            format, offset, location
+        )?;
+        offset += size;
+    }
+    Ok((
+        format!(
+            "Some(wgpu::VertexBufferLayout {{ array_stride: {}, step_mode: wgpu::InputStepMode::Vertex, attributes: &[{}] }})",
+            offset, attributes
+        ),
+        format!("&[{}]", names),
+    ))
+}
+
+fn binding_type(class: StorageClass, inner: &TypeInner) -> Result<String> {
+    Ok(match (class, inner) {
+        (StorageClass::Uniform, _) => "wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None }".to_string(),
+        (StorageClass::Storage { access }, _) => format!(
+            "wgpu::BindingType::Buffer {{ ty: wgpu::BufferBindingType::Storage {{ read_only: {} }}, has_dynamic_offset: false, min_binding_size: None }}",
+            !access.contains(StorageAccess::STORE)
+        ),
+        (StorageClass::Handle, TypeInner::Sampler { comparison }) => format!(
+            "wgpu::BindingType::Sampler {{ filtering: true, comparison: {} }}",
+            comparison
+        ),
+        (StorageClass::Handle, TypeInner::Image { dim, arrayed, class }) => {
+            let (sample_type, multisampled) = match *class {
+                ImageClass::Sampled { kind: ScalarKind::Float, multi } => ("Float { filterable: true }", multi),
+                ImageClass::Sampled { kind: ScalarKind::Sint, multi } => ("Sint", multi),
+                ImageClass::Sampled { kind: ScalarKind::Uint, multi } => ("Uint", multi),
+                ImageClass::Depth { multi } => ("Depth", multi),
+                _ => bail!("unsupported image class {:?}", class),
+            };
+            let view_dimension = match (dim, arrayed) {
+                (ImageDimension::D1, false) => "D1",
+                (ImageDimension::D2, false) => "D2",
+                (ImageDimension::D2, true) => "D2Array",
+                (ImageDimension::D3, false) => "D3",
+                (ImageDimension::Cube, false) => "Cube",
+                (ImageDimension::Cube, true) => "CubeArray",
+                _ => bail!("unsupported image dimension {:?}", dim),
+            };
+            format!(
+                "wgpu::BindingType::Texture {{ sample_type: wgpu::TextureSampleType::{}, view_dimension: wgpu::TextureViewDimension::{}, multisampled: {} }}",
+                sample_type, view_dimension, multisampled
+            )
+        }
+        _ => bail!("unsupported binding {:?} in {:?}", inner, class),
+    })
+}
+
+// one descriptor per bind group, indexed by group, visible to the stages that use the binding
+fn reflect_bind_group_layouts(name: &str, module: &Module, info: &ModuleInfo) -> Result<String> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    for (handle, var) in module.global_variables.iter() {
+        let binding = match &var.binding {
+            Some(binding) => binding,
+            None => continue,
+        };
+        let stages = module
+            .entry_points
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !info.get_entry_point(*index)[handle].is_empty())
+            .map(|(_, entry_point)| match entry_point.stage {
+                ShaderStage::Vertex => "wgpu::ShaderStage::VERTEX.bits()",
+                ShaderStage::Fragment => "wgpu::ShaderStage::FRAGMENT.bits()",
+                ShaderStage::Compute => "wgpu::ShaderStage::COMPUTE.bits()",
+            })
+            .collect::<Vec<_>>();
+        if stages.is_empty() {
+            continue;
+        }
+        let ty = binding_type(var.class, &module.types[var.ty].inner)?;
+        let group = binding.group as usize;
+        if groups.len() <= group {
+            groups.resize(group + 1, Vec::new());
+        }
+        groups[group].push(format!(
+            "wgpu::BindGroupLayoutEntry {{ binding: {}, visibility: wgpu::ShaderStage::from_bits_truncate({}), ty: {}, count: None }}, ",
+            binding.binding,
+            stages.join(" | "),
+            ty
+        ));
+    }
+    let mut layouts = String::new();
+    for (group, entries) in groups.iter().enumerate() {
+        write!(
+            layouts,
+            "wgpu::BindGroupLayoutDescriptor {{ label: Some(\"{} group {}\"), entries: &[{}] }}, ",
+            name,
+            group,
+            entries.concat()
+        )?;
+    }
+    Ok(format!("&[{}]", layouts))
+}
+
+// `challenge.vert` becomes `CHALLENGE_VERT`
+fn const_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
         })
+        .collect()
+}
+
+fn load_manifest() -> Result<Manifest> {
+    println!("cargo:rerun-if-changed={}", MANIFEST);
+    if !Path::new(MANIFEST).exists() {
+        return Ok(Manifest::default());
     }
+    let manifest = read_to_string(MANIFEST)?;
+    toml::from_str(&manifest).with_context(|| format!("invalid {}", MANIFEST))
 }
 
 fn main() -> Result<()> {
+    // picks up shaders added to or removed from the directory
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
     let mut shader_paths = [
         glob("./src/**/*.vert")?,
         glob("./src/**/*.frag")?,
         glob("./src/**/*.comp")?,
+        glob("./src/**/*.wgsl")?,
     ];
     let shaders = shader_paths
         .iter_mut()
@@ -48,20 +275,55 @@ fn main() -> Result<()> {
         .collect::<Vec<Result<_>>>()
         .into_iter()
         .collect::<Result<Vec<_>>>()?;
-    let mut compiler = Compiler::new().context("unable to create shader compiler")?;
-    for shader in shaders {
-        println!(
-            "cargo:rerun-if-changed={:?}",
-            shader.src_path.as_os_str().to_str().unwrap()
-        );
-        let compiled = compiler.compile_into_spirv(
-            &shader.src,
-            shader.kind,
-            &shader.src_path.to_str().unwrap(),
-            "main",
-            None,
+    let manifest = load_manifest()?;
+    let mut variants = Vec::new();
+    for variant in &manifest.variant {
+        let shader = shaders
+            .iter()
+            .find(|shader| shader.src_path == Path::new(SHADER_DIR).join(&variant.shader))
+            .with_context(|| format!("{}: unknown shader {}", variant.name, variant.shader))?;
+        variants.push(shader.variant(variant));
+    }
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
+    let mut generated = String::from("// generated by build.rs\n");
+    for shader in shaders.iter().chain(variants.iter()) {
+        println!("cargo:rerun-if-changed={}", shader.src_path.display());
+        for include in &shader.includes {
+            println!("cargo:rerun-if-changed={}", include.display());
+        }
+        let spv_path = out_dir.join(format!("{}.spv", shader.name));
+        create_dir_all(spv_path.parent().unwrap())?;
+        let (spirv, module, info) = shader.compile()?;
+        write(&spv_path, spirv)?;
+        let mut defines = shader.defines.iter().collect::<Vec<_>>();
+        defines.sort();
+        let (vertex_layout, vertex_inputs) = reflect_vertex_layout(&module)
+            .with_context(|| format!("cannot reflect inputs of {}", shader.name))?;
+        writeln!(
+            generated,
+            "#[allow(dead_code)]\npub(crate) const {}: CompiledShader = CompiledShader {{ name: {:?}, source: {:?}, defines: &{:?}, spirv: include_bytes!({:?}), vertex_layout: {}, vertex_inputs: {}, bind_group_layouts: {} }};",
+            const_name(&shader.name),
+            shader.name,
+            manifest_dir.join(&shader.src_path).display().to_string(),
+            defines,
+            spv_path.display().to_string(),
+            vertex_layout,
+            vertex_inputs,
+            reflect_bind_group_layouts(&shader.name, &module, &info)
+                .with_context(|| format!("cannot reflect bindings of {}", shader.name))?,
         )?;
-        write(shader.spv_path, compiled.as_binary_u8())?;
     }
+    let all = shaders
+        .iter()
+        .chain(variants.iter())
+        .map(|shader| format!("&{}, ", const_name(&shader.name)))
+        .collect::<String>();
+    writeln!(
+        generated,
+        "pub(crate) const ALL: &[&CompiledShader] = &[{}];",
+        all
+    )?;
+    write(out_dir.join("shaders.rs"), generated)?;
     Ok(())
 }
@@ -0,0 +1,372 @@
+use crate::{
+    pipeline_creator::DiffuseTexture,
+    swapchain::State,
+    texture::{ColorSpace, Filter, Mipmaps, SamplerOptions, TextureData},
+};
+use anyhow::{ensure, Result};
+use image::{imageops, DynamicImage, RgbaImage};
+use std::num::NonZeroU32;
+use wgpu::{BindGroupLayout, Device, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, Queue};
+
+// texels around every entry repeating its edge, so filtering at the edge neither
+// bleeds into neighbours nor fades to transparent
+const PADDING: u32 = 1;
+
+pub(crate) type AtlasId = usize;
+
+/// Where an entry ended up: its page and its rectangle in texture coordinates.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AtlasRect {
+    pub(crate) page: usize,
+    pub(crate) min: [f32; 2],
+    pub(crate) max: [f32; 2],
+}
+
+struct Placement {
+    page: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// a row of entries no taller than `height`, filled from the left
+struct Shelf {
+    y: u32,
+    height: u32,
+    next_x: u32,
+}
+
+struct Page {
+    image: RgbaImage,
+    shelves: Vec<Shelf>,
+    texture: Option<DiffuseTexture>,
+    // size of `texture`, pages grow after they are uploaded
+    texture_size: u32,
+    dirty: bool,
+}
+
+impl Page {
+    fn new(size: u32) -> Self {
+        Self {
+            image: RgbaImage::new(size, size),
+            shelves: Vec::new(),
+            texture: None,
+            texture_size: 0,
+            dirty: true,
+        }
+    }
+
+    fn size(&self) -> u32 {
+        self.image.width()
+    }
+
+    // the lowest shelf that fits, or a new one below the others
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let size = self.size();
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| height <= shelf.height && shelf.next_x + width <= size)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = shelf {
+            let x = shelf.next_x;
+            shelf.next_x += width;
+            return Some((x, shelf.y));
+        }
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if width > size || y + height > size {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            next_x: width,
+        });
+        Some((0, y))
+    }
+
+    // doubles the page, keeping every entry at the same texel position
+    fn grow(&mut self) {
+        let mut image = RgbaImage::new(self.size() * 2, self.size() * 2);
+        imageops::replace(&mut image, &self.image, 0, 0);
+        self.image = image;
+        self.dirty = true;
+    }
+}
+
+// fills the padding around the entry at `x`, `y` with the nearest texel of the entry
+fn extrude(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    for py in y - PADDING..y + height + PADDING {
+        for px in x - PADDING..x + width + PADDING {
+            if (x..x + width).contains(&px) && (y..y + height).contains(&py) {
+                continue;
+            }
+            let nearest = *image.get_pixel(px.clamp(x, x + width - 1), py.clamp(y, y + height - 1));
+            image.put_pixel(px, py, nearest);
+        }
+    }
+}
+
+/// Packs images into square RGBA pages on shelves. A page that fills up doubles in
+/// size until `max_page_size`, after which a new page is started.
+pub(crate) struct TextureAtlas {
+    pages: Vec<Page>,
+    placements: Vec<Placement>,
+    page_size: u32,
+    max_page_size: u32,
+}
+
+impl TextureAtlas {
+    pub(crate) fn new(page_size: u32, max_page_size: u32) -> Self {
+        Self {
+            pages: Vec::new(),
+            placements: Vec::new(),
+            page_size,
+            max_page_size: max_page_size.max(page_size),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, image: &RgbaImage) -> Result<AtlasId> {
+        let width = image.width() + 2 * PADDING;
+        let height = image.height() + 2 * PADDING;
+        ensure!(
+            width <= self.max_page_size && height <= self.max_page_size,
+            "{}x{} image doesn't fit a {} page",
+            image.width(),
+            image.height(),
+            self.max_page_size
+        );
+        let mut found = None;
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            if let Some((x, y)) = page.allocate(width, height) {
+                found = Some((index, x, y));
+                break;
+            }
+        }
+        if let (None, Some(last)) = (found, self.pages.len().checked_sub(1)) {
+            let page = &mut self.pages[last];
+            while found.is_none() && page.size() * 2 <= self.max_page_size {
+                page.grow();
+                found = page.allocate(width, height).map(|(x, y)| (last, x, y));
+            }
+        }
+        let (page, x, y) = match found {
+            Some(found) => found,
+            None => {
+                let mut size = self.page_size;
+                while size < width.max(height) {
+                    size *= 2;
+                }
+                let mut page = Page::new(size.min(self.max_page_size));
+                let (x, y) = page
+                    .allocate(width, height)
+                    .expect("a new page fits any entry");
+                self.pages.push(page);
+                (self.pages.len() - 1, x, y)
+            }
+        };
+        let target = &mut self.pages[page];
+        imageops::replace(&mut target.image, image, x + PADDING, y + PADDING);
+        extrude(
+            &mut target.image,
+            x + PADDING,
+            y + PADDING,
+            image.width(),
+            image.height(),
+        );
+        target.dirty = true;
+        self.placements.push(Placement {
+            page,
+            x: x + PADDING,
+            y: y + PADDING,
+            width: image.width(),
+            height: image.height(),
+        });
+        Ok(self.placements.len() - 1)
+    }
+
+    /// The texture coordinates of an entry. They change when its page grows.
+    pub(crate) fn rect(&self, id: AtlasId) -> AtlasRect {
+        let placement = &self.placements[id];
+        let size = self.pages[placement.page].size() as f32;
+        AtlasRect {
+            page: placement.page,
+            min: [placement.x as f32 / size, placement.y as f32 / size],
+            max: [
+                (placement.x + placement.width) as f32 / size,
+                (placement.y + placement.height) as f32 / size,
+            ],
+        }
+    }
+
+    /// Uploads the pages changed since the last call, bound like `create_texture`
+    /// binds to a group of `layout`. Pages that grew get a new texture and bind group.
+    pub(crate) fn upload(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+    ) -> Result<()> {
+        for (index, page) in self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, page)| page.dirty)
+        {
+            page.dirty = false;
+            let size = page.size();
+            match &page.texture {
+                Some(texture) if page.texture_size == size => queue.write_texture(
+                    ImageCopyTexture {
+                        texture: &texture.texture,
+                        mip_level: 0,
+                        origin: Origin3d::ZERO,
+                    },
+                    page.image.as_raw(),
+                    ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(4 * size),
+                        rows_per_image: None,
+                    },
+                    Extent3d {
+                        width: size,
+                        height: size,
+                        depth_or_array_layers: 1,
+                    },
+                ),
+                _ => {
+                    // mipmaps would blend neighbouring entries
+                    let data = TextureData::from_image(
+                        &DynamicImage::ImageRgba8(page.image.clone()),
+                        &format!("atlas page {}", index),
+                        ColorSpace::Srgb,
+                    );
+                    page.texture = Some(State::create_texture(
+                        device,
+                        queue,
+                        layout,
+                        &data,
+                        Mipmaps::None,
+                        SamplerOptions {
+                            filter: Filter::Bilinear,
+                            anisotropy: 1,
+                        },
+                    )?);
+                    page.texture_size = size;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands over the uploaded page textures, for an atlas that won't change again.
+    pub(crate) fn into_textures(self) -> Vec<Option<DiffuseTexture>> {
+        self.pages.into_iter().map(|page| page.texture).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    // the placements with their padding, which must not overlap each other
+    fn padded(atlas: &TextureAtlas) -> Vec<(usize, u32, u32, u32, u32)> {
+        atlas
+            .placements
+            .iter()
+            .map(|placement| {
+                (
+                    placement.page,
+                    placement.x - PADDING,
+                    placement.y - PADDING,
+                    placement.x + placement.width + PADDING,
+                    placement.y + placement.height + PADDING,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn places_entries_without_overlap() {
+        let mut atlas = TextureAtlas::new(64, 64);
+        for i in 0..20 {
+            atlas.insert(&image(3 + i % 7, 2 + i % 5, i as u8)).unwrap();
+        }
+        assert_eq!(atlas.pages.len(), 1);
+        let rects = padded(&atlas);
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.3 <= 64 && a.4 <= 64, "{:?} is outside the page", a);
+            for b in &rects[i + 1..] {
+                let disjoint = a.3 <= b.1 || b.3 <= a.1 || a.4 <= b.2 || b.4 <= a.2;
+                assert!(disjoint, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        for (id, placement) in atlas.placements.iter().enumerate() {
+            let pixel = atlas.pages[0].image.get_pixel(placement.x, placement.y);
+            assert_eq!(pixel[0], id as u8);
+        }
+    }
+
+    #[test]
+    fn grows_a_full_page_and_moves_its_rects() {
+        let mut atlas = TextureAtlas::new(16, 64);
+        let first = atlas.insert(&image(10, 10, 1)).unwrap();
+        assert_eq!(atlas.rect(first).max, [11.0 / 16.0; 2]);
+        let second = atlas.insert(&image(10, 10, 2)).unwrap();
+        assert_eq!(atlas.pages.len(), 1);
+        assert_eq!(atlas.pages[0].size(), 32);
+        // the texels stay put, so the texture coordinates halve
+        assert_eq!(atlas.rect(first).min, [1.0 / 32.0; 2]);
+        assert_eq!(atlas.rect(first).max, [11.0 / 32.0; 2]);
+        assert_eq!(atlas.rect(second).page, 0);
+        assert_eq!(atlas.pages[0].image.get_pixel(1, 1)[0], 1);
+    }
+
+    #[test]
+    fn spills_into_a_new_page_at_the_maximum_size() {
+        let mut atlas = TextureAtlas::new(16, 32);
+        let ids = (0..5)
+            .map(|i| atlas.insert(&image(14, 14, i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(atlas.pages.len(), 2);
+        assert_eq!(atlas.pages[0].size(), 32);
+        let pages = ids
+            .iter()
+            .map(|&id| atlas.rect(id).page)
+            .collect::<Vec<_>>();
+        assert_eq!(pages, vec![0, 0, 0, 0, 1]);
+        assert_eq!(atlas.pages[1].size(), 16);
+        assert!(atlas.insert(&image(31, 31, 0)).is_err());
+    }
+
+    #[test]
+    fn extrudes_edges_into_the_padding() {
+        let mut atlas = TextureAtlas::new(16, 16);
+        let mut entry = image(2, 2, 0);
+        entry.put_pixel(0, 0, Rgba([10, 0, 0, 255]));
+        entry.put_pixel(1, 0, Rgba([20, 0, 0, 255]));
+        entry.put_pixel(0, 1, Rgba([30, 0, 0, 255]));
+        entry.put_pixel(1, 1, Rgba([40, 0, 0, 255]));
+        atlas.insert(&entry).unwrap();
+        let page = &atlas.pages[0].image;
+        let row = |y| (0..4).map(|x| page.get_pixel(x, y)[0]).collect::<Vec<_>>();
+        assert_eq!(row(0), vec![10, 10, 20, 20]);
+        assert_eq!(row(1), vec![10, 10, 20, 20]);
+        assert_eq!(row(2), vec![30, 30, 40, 40]);
+        assert_eq!(row(3), vec![30, 30, 40, 40]);
+        // nothing beyond the padding
+        assert_eq!(page.get_pixel(4, 0)[3], 0);
+    }
+}
@@ -0,0 +1,15 @@
+#version 450
+
+// a single triangle covering the whole target
+const vec2 tex_coords[3] = vec2[3] (
+    vec2(0.0, 0.0),
+    vec2(2.0, 0.0),
+    vec2(0.0, 2.0)
+);
+
+layout(location = 0) out vec2 v_tex_coords;
+
+void main() {
+    v_tex_coords = tex_coords[gl_VertexIndex];
+    gl_Position = vec4(v_tex_coords.x * 2.0 - 1.0, 1.0 - v_tex_coords.y * 2.0, 0.0, 1.0);
+}
@@ -1,83 +1,581 @@
+use crate::shaders::{self, CompiledShader};
+use cgmath::{Matrix4, Rad, Vector3};
+use std::{f32::consts::PI, mem};
+pub use vertex_format_derive::VertexFormat;
 use wgpu::{self, BufferAddress, InputStepMode, VertexAttribute, VertexBufferLayout};
 
+pub mod generate;
+
+/// A vertex buffer element, usually derived with `#[derive(VertexFormat)]`.
+pub trait VertexFormat: bytemuck::Pod {
+    const ATTRIBUTES: &'static [VertexAttribute];
+
+    // the field each attribute comes from, `<field>_<column>` for matrix columns
+    const ATTRIBUTE_NAMES: &'static [&'static str];
+
+    const STEP_MODE: InputStepMode = InputStepMode::Vertex;
+
+    const LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+        array_stride: mem::size_of::<Self>() as BufferAddress,
+        step_mode: Self::STEP_MODE,
+        attributes: Self::ATTRIBUTES,
+    };
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        Self::LAYOUT
+    }
+}
+
+/// The vertex format of a field type, for fields without `#[vertex(format = ..)]`.
+pub trait VertexAttributeType {
+    const FORMAT: wgpu::VertexFormat;
+}
+
+macro_rules! vertex_attribute_types {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(impl VertexAttributeType for $ty {
+            const FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::$format;
+        })*
+    };
+}
+
+vertex_attribute_types! {
+    f32 => Float32,
+    [f32; 2] => Float32x2,
+    [f32; 3] => Float32x3,
+    [f32; 4] => Float32x4,
+    u32 => Uint32,
+    [u32; 2] => Uint32x2,
+    [u32; 3] => Uint32x3,
+    [u32; 4] => Uint32x4,
+    i32 => Sint32,
+    [i32; 2] => Sint32x2,
+    [i32; 3] => Sint32x3,
+    [i32; 4] => Sint32x4,
+}
+
+/// Whether `layout` is what `shader` reads from its first vertex buffer.
+pub const fn layout_matches(layout: &VertexBufferLayout, shader: &CompiledShader) -> bool {
+    let expected = match &shader.vertex_layout {
+        Some(expected) => expected,
+        None => return false,
+    };
+    if layout.array_stride != expected.array_stride
+        || layout.attributes.len() != expected.attributes.len()
+    {
+        return false;
+    }
+    let mut i = 0;
+    while i < layout.attributes.len() {
+        let (attribute, expected) = (&layout.attributes[i], &expected.attributes[i]);
+        if attribute.offset != expected.offset
+            || attribute.shader_location != expected.shader_location
+            || attribute.format as u32 != expected.format as u32
+        {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Whether the attributes of `V` feed the shader inputs named after their fields,
+/// `a_position` for `position`, so fields swapped in the struct but not in the
+/// shader are caught even when their formats agree.
+pub const fn names_match<V: VertexFormat>(shader: &CompiledShader) -> bool {
+    let (attributes, inputs) = match &shader.vertex_layout {
+        Some(layout) => (layout.attributes, shader.vertex_inputs),
+        None => return false,
+    };
+    let mut i = 0;
+    while i < V::ATTRIBUTES.len() {
+        let mut j = 0;
+        while j < attributes.len()
+            && attributes[j].shader_location != V::ATTRIBUTES[i].shader_location
+        {
+            j += 1;
+        }
+        if j == attributes.len()
+            || !is_input_name(inputs[j].as_bytes(), V::ATTRIBUTE_NAMES[i].as_bytes())
+        {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// whether `input` is `field` with the `a_` prefix of the vertex shader inputs
+const fn is_input_name(input: &[u8], field: &[u8]) -> bool {
+    const PREFIX: &[u8] = b"a_";
+    if input.len() != PREFIX.len() + field.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < input.len() {
+        let expected = if i < PREFIX.len() {
+            PREFIX[i]
+        } else {
+            field[i - PREFIX.len()]
+        };
+        if input[i] != expected {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Whether `layouts` together bind every input of `shader` exactly once, for
+/// shaders reading several buffers like instanced ones: the first buffer steps per
+/// vertex, the others per instance, and every attribute fits its buffer's stride
+/// without overlapping another.
+pub const fn layouts_match(layouts: &[VertexBufferLayout], shader: &CompiledShader) -> bool {
+    let expected = match &shader.vertex_layout {
+        Some(expected) => expected.attributes,
+        None => return false,
+    };
+    let mut count = 0;
+    let mut i = 0;
+    while i < layouts.len() {
+        let layout = &layouts[i];
+        let step_mode = if i == 0 {
+            InputStepMode::Vertex
+        } else {
+            InputStepMode::Instance
+        };
+        if layout.step_mode as u32 != step_mode as u32 {
+            return false;
+        }
+        let attributes = layout.attributes;
+        let mut j = 0;
+        while j < attributes.len() {
+            let attribute = &attributes[j];
+            let end = attribute.offset + attribute.format.size();
+            if end > layout.array_stride {
+                return false;
+            }
+            let mut k = 0;
+            while k < j {
+                let other = &attributes[k];
+                if attribute.offset < other.offset + other.format.size() && other.offset < end {
+                    return false;
+                }
+                k += 1;
+            }
+            let mut k = 0;
+            while k < expected.len()
+                && (expected[k].shader_location != attribute.shader_location
+                    || expected[k].format as u32 != attribute.format as u32)
+            {
+                k += 1;
+            }
+            if k == expected.len() {
+                return false;
+            }
+            // each location once, so the count below means every input is bound
+            let mut previous = 0;
+            while previous < i {
+                let others = layouts[previous].attributes;
+                if binds_location(others, others.len(), attribute.shader_location) {
+                    return false;
+                }
+                previous += 1;
+            }
+            if binds_location(attributes, j, attribute.shader_location) {
+                return false;
+            }
+            j += 1;
+        }
+        count += attributes.len();
+        i += 1;
+    }
+    count == expected.len()
+}
+
+// whether one of the first `count` attributes is at `location`
+const fn binds_location(attributes: &[VertexAttribute], count: usize, location: u32) -> bool {
+    let mut i = 0;
+    while i < count {
+        if attributes[i].shader_location == location {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Vertex types with a position, for the tools in `mesh_tools`.
+pub trait HasPosition {
+    fn position(&self) -> [f32; 3];
+}
+
+macro_rules! has_position {
+    ($($ty:ty),*) => {
+        $(impl HasPosition for $ty {
+            fn position(&self) -> [f32; 3] {
+                self.position
+            }
+        })*
+    };
+}
+
+has_position!(Vertex, TexturedVertex, ModelVertex);
+
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
 pub struct Vertex {
     position: [f32; 3],
     color: [f32; 3],
 }
 
 impl Vertex {
-    pub fn desc<'a>() -> VertexBufferLayout<'a> {
-        VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as BufferAddress,
-            step_mode: InputStepMode::Vertex,
-            attributes: &[
-                VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self { position, color }
+    }
+}
+
+// fails the build when Vertex and the inputs of challenge.vert disagree
+const _: () = assert!(
+    layout_matches(&Vertex::LAYOUT, &shaders::CHALLENGE_VERT)
+        && names_match::<Vertex>(&shaders::CHALLENGE_VERT),
+    "Vertex does not match the inputs of challenge.vert"
+);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+pub struct TexturedVertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+}
+
+impl TexturedVertex {
+    pub fn new(position: [f32; 3], tex_coords: [f32; 2]) -> Self {
+        Self {
+            position,
+            tex_coords,
         }
     }
 }
 
-pub const PENTAGON_VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [-0.086_824_1, 0.492_403_86, 0.0],
-        color: [1.0, 0.0, 0.0],
-    },
-    Vertex {
-        position: [-0.495_134_06, 0.069_585_845, 0.0],
-        color: [0.0, 1.0, 0.0],
-    },
-    Vertex {
-        position: [-0.219_185_49, -0.449_397_06, 0.0],
-        color: [0.0, 0.0, 1.0],
-    },
-    Vertex {
-        position: [0.359_669_98, -0.347_329_1, 0.0],
-        color: [1.0, 0.0, 0.0],
-    },
-    Vertex {
-        position: [0.441_473_72, 0.234_735_9, 0.0],
-        color: [0.0, 1.0, 0.0],
-    },
-];
+const _: () = assert!(
+    layout_matches(&TexturedVertex::LAYOUT, &shaders::TEXTURED_VERT)
+        && names_match::<TexturedVertex>(&shaders::TEXTURED_VERT),
+    "TexturedVertex does not match the inputs of textured.vert"
+);
 
-pub const PENTAGON_INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 3],
+    // MikkTSpace, w is the sign of the bitangent
+    pub tangent: [f32; 4],
+}
 
-pub const HEXAGON_VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [0.0, 0.5, 0.0],
-        color: [1.0, 0.0, 0.0],
-    },
-    Vertex {
-        position: [-0.5, 0.25, 0.0],
-        color: [0.0, 1.0, 0.0],
-    },
-    Vertex {
-        position: [-0.5, -0.25, 0.0],
-        color: [0.0, 0.0, 1.0],
+const _: () = assert!(
+    layout_matches(&ModelVertex::LAYOUT, &shaders::MODEL_VERT)
+        && names_match::<ModelVertex>(&shaders::MODEL_VERT),
+    "ModelVertex does not match the inputs of model.vert"
+);
+
+/// Where and in which color to draw one instance of a mesh.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+#[vertex(instance)]
+pub struct Instance {
+    // after the inputs of `Vertex`
+    #[vertex(location = 2)]
+    pub model: [[f32; 4]; 4],
+    pub tint: [f32; 3],
+}
+
+const _: () = assert!(
+    layouts_match(
+        &[Vertex::LAYOUT, Instance::LAYOUT],
+        &shaders::INSTANCED_VERT
+    ) && names_match::<Vertex>(&shaders::INSTANCED_VERT)
+        && names_match::<Instance>(&shaders::INSTANCED_VERT),
+    "Vertex and Instance do not match the inputs of instanced.vert"
+);
+
+// texture coordinates start at the top left of the image
+pub const QUAD_VERTICES: &[TexturedVertex] = &[
+    TexturedVertex {
+        position: [-0.5, 0.5, 0.0],
+        tex_coords: [0.0, 0.0],
     },
-    Vertex {
-        position: [0.0, -0.5, 0.0],
-        color: [1.0, 0.0, 0.0],
+    TexturedVertex {
+        position: [-0.5, -0.5, 0.0],
+        tex_coords: [0.0, 1.0],
     },
-    Vertex {
-        position: [0.5, -0.25, 0.0],
-        color: [0.0, 1.0, 0.0],
+    TexturedVertex {
+        position: [0.5, -0.5, 0.0],
+        tex_coords: [1.0, 1.0],
     },
-    Vertex {
-        position: [0.5, 0.25, 0.0],
-        color: [0.0, 0.0, 1.0],
+    TexturedVertex {
+        position: [0.5, 0.5, 0.0],
+        tex_coords: [1.0, 0.0],
     },
 ];
 
-pub const HEXAGON_INDICES: &[u16] = &[1, 2, 3, 0, 1, 3, 0, 3, 5, 5, 3, 4];
+pub const QUAD_INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
+
+/// One quad per texture rectangle, side by side across the screen, each as tall as
+/// its `aspect` (height / width) allows.
+pub fn sprite_row(rects: &[([f32; 2], [f32; 2], f32)]) -> (Vec<TexturedVertex>, Vec<u16>) {
+    let cell = 1.8 / rects.len() as f32;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (index, (min, max, aspect)) in rects.iter().enumerate() {
+        let width = cell.min(1.8 / aspect) * 0.9;
+        let height = width * aspect;
+        let left = -0.9 + cell * index as f32 + (cell - width) / 2.0;
+        let first = vertices.len() as u16;
+        vertices.extend_from_slice(&[
+            TexturedVertex::new([left, height / 2.0, 0.0], [min[0], min[1]]),
+            TexturedVertex::new([left, -height / 2.0, 0.0], [min[0], max[1]]),
+            TexturedVertex::new([left + width, -height / 2.0, 0.0], [max[0], max[1]]),
+            TexturedVertex::new([left + width, height / 2.0, 0.0], [max[0], min[1]]),
+        ]);
+        indices.extend(QUAD_INDICES.iter().map(|index| first + index));
+    }
+    (vertices, indices)
+}
+
+// a pseudo random number in 0..1 for every index and seed
+fn hash(index: u32, seed: u32) -> f32 {
+    let mut x = index.wrapping_mul(0x9E37_79B9) ^ seed.wrapping_mul(0x85EB_CA6B);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x2C1B_3C6D);
+    x ^= x >> 12;
+    x as f32 / u32::MAX as f32
+}
+
+/// `count` instances of a 1 unit mesh on a square grid filling -1..1, each with
+/// its own tint and turning at its own speed, `time` seconds in.
+pub fn crowd(count: u32, time: f32) -> Vec<Instance> {
+    let side = (count as f32).sqrt().ceil() as u32;
+    let cell = 2.0 / side as f32;
+    (0..count)
+        .map(|index| {
+            let (column, row) = (index % side, index / side);
+            let centre = Vector3::new(
+                -1.0 + cell * (column as f32 + 0.5),
+                1.0 - cell * (row as f32 + 0.5),
+                0.0,
+            );
+            // up to half a turn per second either way
+            let speed = PI * (2.0 * hash(index, 0) - 1.0);
+            let model = Matrix4::from_translation(centre)
+                * Matrix4::from_angle_z(Rad(speed * time))
+                * Matrix4::from_scale(0.9 * cell);
+            Instance {
+                model: model.into(),
+                tint: [1, 2, 3].map(|seed| 0.4 + 0.6 * hash(index, seed)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::vertex_attr_array;
+
+    const VERTEX: [VertexAttribute; 2] = vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+    const INSTANCE: [VertexAttribute; 5] = vertex_attr_array![
+        2 => Float32x4, 3 => Float32x4, 4 => Float32x4, 5 => Float32x4, 6 => Float32x3
+    ];
+
+    fn layout(
+        attributes: &'static [VertexAttribute],
+        array_stride: BufferAddress,
+        step_mode: InputStepMode,
+    ) -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride,
+            step_mode,
+            attributes,
+        }
+    }
+
+    #[test]
+    fn layouts_match_instanced_inputs() {
+        let layouts = [
+            layout(&VERTEX, 24, InputStepMode::Vertex),
+            layout(&INSTANCE, 76, InputStepMode::Instance),
+        ];
+        assert!(layouts_match(&layouts, &shaders::INSTANCED_VERT));
+    }
+
+    #[test]
+    fn layouts_match_rejects_wrong_step_modes() {
+        let layouts = [
+            layout(&VERTEX, 24, InputStepMode::Vertex),
+            layout(&INSTANCE, 76, InputStepMode::Vertex),
+        ];
+        assert!(!layouts_match(&layouts, &shaders::INSTANCED_VERT));
+        let layouts = [
+            layout(&VERTEX, 24, InputStepMode::Instance),
+            layout(&INSTANCE, 76, InputStepMode::Instance),
+        ];
+        assert!(!layouts_match(&layouts, &shaders::INSTANCED_VERT));
+    }
+
+    #[test]
+    fn layouts_match_rejects_attributes_outside_the_stride() {
+        let layouts = [
+            layout(&VERTEX, 20, InputStepMode::Vertex),
+            layout(&INSTANCE, 76, InputStepMode::Instance),
+        ];
+        assert!(!layouts_match(&layouts, &shaders::INSTANCED_VERT));
+    }
+
+    #[test]
+    fn layouts_match_rejects_overlapping_attributes() {
+        const OVERLAPPING: [VertexAttribute; 2] = [
+            VertexAttribute {
+                format: wgpu::VertexFormat::Float32x3,
+                offset: 0,
+                shader_location: 0,
+            },
+            VertexAttribute {
+                format: wgpu::VertexFormat::Float32x3,
+                offset: 8,
+                shader_location: 1,
+            },
+        ];
+        let layouts = [
+            layout(&OVERLAPPING, 24, InputStepMode::Vertex),
+            layout(&INSTANCE, 76, InputStepMode::Instance),
+        ];
+        assert!(!layouts_match(&layouts, &shaders::INSTANCED_VERT));
+    }
+
+    #[test]
+    fn layouts_match_rejects_duplicate_locations() {
+        // binds location 1 twice and leaves location 6 out, with the right count
+        const DUPLICATE: [VertexAttribute; 5] = vertex_attr_array![
+            2 => Float32x4, 3 => Float32x4, 4 => Float32x4, 5 => Float32x4, 1 => Float32x3
+        ];
+        let layouts = [
+            layout(&VERTEX, 24, InputStepMode::Vertex),
+            layout(&DUPLICATE, 76, InputStepMode::Instance),
+        ];
+        assert!(!layouts_match(&layouts, &shaders::INSTANCED_VERT));
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+    struct Mixed {
+        position: [f32; 3],
+        #[vertex(skip)]
+        id: u32,
+        #[vertex(format = "Unorm8x4")]
+        color: [u8; 4],
+        #[vertex(location = 5)]
+        weights: [f32; 2],
+        layer: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+    #[vertex(instance)]
+    struct Transform {
+        scale: f32,
+        matrix: [[f32; 3]; 3],
+        tint: [f32; 4],
+    }
+
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+    struct Packed {
+        #[vertex(skip)]
+        id: u16,
+        position: [f32; 3],
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, VertexFormat)]
+    struct Tuple([f32; 2], [u32; 2]);
+
+    fn attributes<V: VertexFormat>() -> Vec<(BufferAddress, wgpu::VertexFormat, u32)> {
+        V::ATTRIBUTES
+            .iter()
+            .map(|attribute| {
+                (
+                    attribute.offset,
+                    attribute.format,
+                    attribute.shader_location,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn derives_offsets_locations_and_formats() {
+        use wgpu::VertexFormat::*;
+        assert_eq!(
+            attributes::<Mixed>(),
+            vec![
+                (0, Float32x3, 0),
+                (16, Unorm8x4, 1),
+                (20, Float32x2, 5),
+                (28, Sint32, 6),
+            ]
+        );
+        assert_eq!(
+            Mixed::ATTRIBUTE_NAMES,
+            &["position", "color", "weights", "layer"]
+        );
+        assert_eq!(Mixed::LAYOUT.array_stride, 32);
+        assert!(Mixed::STEP_MODE == InputStepMode::Vertex);
+        assert_eq!(
+            attributes::<Tuple>(),
+            vec![(0, Float32x2, 0), (8, Uint32x2, 1)]
+        );
+        assert_eq!(Tuple::ATTRIBUTE_NAMES, &["0", "1"]);
+    }
+
+    #[test]
+    fn derives_one_location_per_matrix_column() {
+        use wgpu::VertexFormat::*;
+        assert_eq!(
+            attributes::<Transform>(),
+            vec![
+                (0, Float32, 0),
+                (4, Float32x3, 1),
+                (16, Float32x3, 2),
+                (28, Float32x3, 3),
+                (40, Float32x4, 4),
+            ]
+        );
+        assert_eq!(
+            Transform::ATTRIBUTE_NAMES,
+            &["scale", "matrix_0", "matrix_1", "matrix_2", "tint"]
+        );
+        assert!(Transform::STEP_MODE == InputStepMode::Instance);
+        assert_eq!(Transform::LAYOUT.array_stride, 56);
+    }
+
+    #[test]
+    fn derives_packed_offsets() {
+        assert_eq!(
+            attributes::<Packed>(),
+            vec![(2, wgpu::VertexFormat::Float32x3, 0)]
+        );
+        assert_eq!(Packed::LAYOUT.array_stride, 14);
+    }
+
+    #[test]
+    fn names_match_the_shader_inputs() {
+        assert!(names_match::<Vertex>(&shaders::CHALLENGE_VERT));
+        assert!(names_match::<Instance>(&shaders::INSTANCED_VERT));
+        // inputs at the same locations under other names
+        assert!(!names_match::<TexturedVertex>(&shaders::CHALLENGE_VERT));
+        assert!(!names_match::<Vertex>(&shaders::MODEL_VERT));
+    }
+}
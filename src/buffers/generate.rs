@@ -0,0 +1,428 @@
+//! Procedural meshes, centred on the origin with y up. Triangles wind
+//! counter-clockwise seen from outside; flat shapes lie in the xy plane facing +z.
+
+use super::Vertex;
+use std::{collections::HashMap, f32::consts::PI};
+
+/// How the vertices of a generated mesh are colored.
+#[derive(Clone, Copy)]
+pub enum Colors<'a> {
+    Solid([f32; 3]),
+    // repeats the colors in vertex order
+    Cycle(&'a [[f32; 3]]),
+    // the position mapped from -1..1 to 0..1 on each channel
+    Position,
+}
+
+impl Colors<'_> {
+    /// Red, green and blue in turn.
+    pub const RGB: Colors<'static> =
+        Colors::Cycle(&[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+    fn vertex(&self, index: usize, position: [f32; 3]) -> Vertex {
+        let color = match self {
+            Colors::Solid(color) => *color,
+            Colors::Cycle(colors) => colors[index % colors.len()],
+            Colors::Position => position.map(|p| 0.5 + 0.5 * p),
+        };
+        Vertex::new(position, color)
+    }
+}
+
+fn colored(positions: Vec<[f32; 3]>, colors: Colors) -> Vec<Vertex> {
+    positions
+        .into_iter()
+        .enumerate()
+        .map(|(index, position)| colors.vertex(index, position))
+        .collect()
+}
+
+// two triangles for every cell of a (rows + 1) x (columns + 1) vertex grid stored
+// row by row, with `skip` deciding which triangles of a row collapse to a point
+fn grid_indices(columns: u32, rows: u32, skip: impl Fn(u32) -> (bool, bool)) -> Vec<u32> {
+    let mut indices = Vec::new();
+    for row in 0..rows {
+        let (skip_first, skip_second) = skip(row);
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let b = a + columns + 1;
+            if !skip_first {
+                indices.extend_from_slice(&[a, b, b + 1]);
+            }
+            if !skip_second {
+                indices.extend_from_slice(&[a, b + 1, a + 1]);
+            }
+        }
+    }
+    indices
+}
+
+/// A regular polygon with its first corner at the top, as a fan from that corner.
+pub fn polygon(sides: u32, radius: f32, colors: Colors) -> (Vec<Vertex>, Vec<u32>) {
+    let sides = sides.max(3);
+    let positions = (0..sides)
+        .map(|side| {
+            let angle = PI / 2.0 + 2.0 * PI * side as f32 / sides as f32;
+            [radius * angle.cos(), radius * angle.sin(), 0.0]
+        })
+        .collect();
+    let indices = (1..sides - 1).flat_map(|i| [0, i, i + 1]).collect();
+    (colored(positions, colors), indices)
+}
+
+/// A `width` by `height` rectangle split into `columns` by `rows` cells.
+pub fn grid(
+    columns: u32,
+    rows: u32,
+    width: f32,
+    height: f32,
+    colors: Colors,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    let mut positions = Vec::new();
+    for row in 0..=rows {
+        for column in 0..=columns {
+            positions.push([
+                width * (column as f32 / columns as f32 - 0.5),
+                height * (0.5 - row as f32 / rows as f32),
+                0.0,
+            ]);
+        }
+    }
+    let indices = grid_indices(columns, rows, |_| (false, false));
+    (colored(positions, colors), indices)
+}
+
+/// A cube with four vertices per face, so every face can have its own colors.
+pub fn cube(size: f32, colors: Colors) -> (Vec<Vertex>, Vec<u32>) {
+    // the normal of each face and two edges with u x v = normal
+    const FACES: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    let half = size / 2.0;
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for (normal, u, v) in FACES.iter() {
+        let first = positions.len() as u32;
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            positions
+                .push([0, 1, 2].map(|axis| half * (normal[axis] + su * u[axis] + sv * v[axis])));
+        }
+        indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    (colored(positions, colors), indices)
+}
+
+/// A sphere of `rings` bands from pole to pole, each split into `segments`.
+pub fn uv_sphere(
+    radius: f32,
+    segments: u32,
+    rings: u32,
+    colors: Colors,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let mut positions = Vec::new();
+    for ring in 0..=rings {
+        let polar = PI * ring as f32 / rings as f32;
+        for segment in 0..=segments {
+            let azimuth = 2.0 * PI * segment as f32 / segments as f32;
+            positions.push([
+                radius * polar.sin() * azimuth.cos(),
+                radius * polar.cos(),
+                -radius * polar.sin() * azimuth.sin(),
+            ]);
+        }
+    }
+    // the triangles touching a pole have no area
+    let indices = grid_indices(segments, rings, |ring| (ring == rings - 1, ring == 0));
+    (colored(positions, colors), indices)
+}
+
+/// An icosahedron with every triangle split in four `subdivisions` times and the
+/// vertices pushed out onto the sphere.
+pub fn icosphere(radius: f32, subdivisions: u32, colors: Colors) -> (Vec<Vertex>, Vec<u32>) {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut positions: Vec<[f32; 3]> = vec![
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ];
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+    for _ in 0..subdivisions {
+        // edges shared by two triangles get one midpoint
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let (a, b) = (positions[a as usize], positions[b as usize]);
+                positions.push([0, 1, 2].map(|axis| (a[axis] + b[axis]) / 2.0));
+                positions.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+    for position in &mut positions {
+        let length = position.iter().map(|p| p * p).sum::<f32>().sqrt();
+        *position = position.map(|p| radius * p / length);
+    }
+    (colored(positions, colors), triangles.concat())
+}
+
+/// A capped cylinder along the y axis with `segments` sides.
+pub fn cylinder(
+    radius: f32,
+    height: f32,
+    segments: u32,
+    colors: Colors,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let ring = |y: f32| {
+        (0..=segments).map(move |segment| {
+            let angle = 2.0 * PI * segment as f32 / segments as f32;
+            [radius * angle.cos(), y, -radius * angle.sin()]
+        })
+    };
+    let top = height / 2.0;
+    // the side, then each cap as a centre and its own ring
+    let mut positions: Vec<_> = ring(top).chain(ring(-top)).collect();
+    let mut indices = grid_indices(segments, 1, |_| (false, false));
+    for (y, flip) in [(top, false), (-top, true)] {
+        let centre = positions.len() as u32;
+        positions.push([0.0, y, 0.0]);
+        positions.extend(ring(y));
+        for segment in 0..segments {
+            let (a, b) = (centre + 1 + segment, centre + 2 + segment);
+            indices.extend_from_slice(&if flip { [centre, b, a] } else { [centre, a, b] });
+        }
+    }
+    (colored(positions, colors), indices)
+}
+
+/// A torus around the y axis: a tube of `tube_radius` split into `sides`, swept
+/// along a circle of `radius` split into `segments`.
+pub fn torus(
+    radius: f32,
+    tube_radius: f32,
+    segments: u32,
+    sides: u32,
+    colors: Colors,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+    let mut positions = Vec::new();
+    for segment in 0..=segments {
+        let angle = 2.0 * PI * segment as f32 / segments as f32;
+        let (x, z) = (angle.cos(), -angle.sin());
+        for side in 0..=sides {
+            let tube_angle = 2.0 * PI * side as f32 / sides as f32;
+            let distance = radius + tube_radius * tube_angle.cos();
+            positions.push([distance * x, tube_radius * tube_angle.sin(), distance * z]);
+        }
+    }
+    let indices = grid_indices(sides, segments, |_| (false, false));
+    (colored(positions, colors), indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Vector3};
+
+    type Mesh = (Vec<Vertex>, Vec<u32>);
+
+    fn check_indices((vertices, indices): &Mesh) {
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices
+            .iter()
+            .all(|&index| (index as usize) < vertices.len()));
+    }
+
+    // every triangle with an area, with its normal from the winding and its centre
+    fn triangles((vertices, indices): &Mesh) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        indices
+            .chunks(3)
+            .filter_map(|triangle| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+                let normal = (b - a).cross(c - a);
+                (normal.magnitude() > 1e-6).then(|| (normal.normalize(), (a + b + c) / 3.0))
+            })
+            .collect()
+    }
+
+    // closed shapes around the origin face away from it
+    fn check_outward(mesh: &Mesh) {
+        for (normal, centre) in triangles(mesh) {
+            assert!(
+                normal.dot(centre) > 0.0,
+                "{:?} faces inwards at {:?}",
+                normal,
+                centre
+            );
+        }
+    }
+
+    fn check_facing_z(mesh: &Mesh) {
+        for (normal, _) in triangles(mesh) {
+            assert!(normal.z > 0.99, "{:?} doesn't face +z", normal);
+        }
+    }
+
+    #[test]
+    fn polygon_is_a_fan_facing_z() {
+        let mesh = polygon(6, 0.5, Colors::RGB);
+        check_indices(&mesh);
+        assert_eq!(mesh.0.len(), 6);
+        assert_eq!(mesh.1.len(), 3 * 4);
+        assert!(
+            (Vector3::from(mesh.0[0].position) - Vector3::new(0.0, 0.5, 0.0)).magnitude() < 1e-6
+        );
+        check_facing_z(&mesh);
+        // too few sides make a triangle
+        assert_eq!(polygon(1, 0.5, Colors::RGB).1.len(), 3);
+    }
+
+    #[test]
+    fn colors_vertices_solid_in_turn_or_by_position() {
+        let (vertices, _) = polygon(4, 1.0, Colors::Solid([0.25, 0.5, 0.75]));
+        assert!(vertices.iter().all(|v| v.color == [0.25, 0.5, 0.75]));
+        let (vertices, _) = polygon(4, 1.0, Colors::RGB);
+        let colors = vertices.iter().map(|v| v.color).collect::<Vec<_>>();
+        assert_eq!(colors[0], colors[3]);
+        assert_eq!(
+            colors[..3],
+            [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+        );
+        let (vertices, _) = polygon(4, 1.0, Colors::Position);
+        let top = vertices[0].color;
+        assert!((top[0] - 0.5).abs() < 1e-6 && top[1] == 1.0 && top[2] == 0.5);
+    }
+
+    #[test]
+    fn grid_covers_its_cells() {
+        let mesh = grid(3, 2, 1.0, 2.0, Colors::RGB);
+        check_indices(&mesh);
+        assert_eq!(mesh.0.len(), 4 * 3);
+        assert_eq!(mesh.1.len(), 3 * 2 * 3 * 2);
+        check_facing_z(&mesh);
+        assert_eq!(mesh.0[0].position, [-0.5, 1.0, 0.0]);
+        assert_eq!(mesh.0[11].position, [0.5, -1.0, 0.0]);
+        let area: f32 = mesh
+            .1
+            .chunks(3)
+            .map(|triangle| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| Vector3::from(mesh.0[triangle[i] as usize].position));
+                (b - a).cross(c - a).magnitude() / 2.0
+            })
+            .sum();
+        assert!((area - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cube_faces_outwards() {
+        let mesh = cube(2.0, Colors::Position);
+        check_indices(&mesh);
+        assert_eq!(mesh.0.len(), 24);
+        assert_eq!(mesh.1.len(), 36);
+        check_outward(&mesh);
+        assert!(mesh
+            .0
+            .iter()
+            .all(|vertex| vertex.position.iter().all(|p| p.abs() == 1.0)));
+        // Position colors map -1..1 to 0..1
+        assert!(mesh
+            .0
+            .iter()
+            .all(|vertex| vertex.color.iter().all(|c| *c == 0.0 || *c == 1.0)));
+    }
+
+    #[test]
+    fn uv_sphere_faces_outwards() {
+        let mesh = uv_sphere(0.5, 8, 4, Colors::Position);
+        check_indices(&mesh);
+        assert_eq!(mesh.0.len(), 9 * 5);
+        // the pole rows have one triangle per cell, the others two
+        assert_eq!(mesh.1.len(), 3 * 8 * (2 * 4 - 2));
+        assert_eq!(triangles(&mesh).len(), 8 * (2 * 4 - 2));
+        check_outward(&mesh);
+        assert!(mesh
+            .0
+            .iter()
+            .all(|vertex| (Vector3::from(vertex.position).magnitude() - 0.5).abs() < 1e-5));
+    }
+
+    #[test]
+    fn icosphere_subdivides_into_four() {
+        let mesh = icosphere(0.5, 2, Colors::Position);
+        check_indices(&mesh);
+        // V = 10 * 4^n + 2 for a subdivided icosahedron
+        assert_eq!(mesh.0.len(), 10 * 16 + 2);
+        assert_eq!(mesh.1.len(), 3 * 20 * 16);
+        check_outward(&mesh);
+        assert!(mesh
+            .0
+            .iter()
+            .all(|vertex| (Vector3::from(vertex.position).magnitude() - 0.5).abs() < 1e-5));
+    }
+
+    #[test]
+    fn cylinder_sides_and_caps_face_outwards() {
+        let mesh = cylinder(0.4, 1.0, 6, Colors::Position);
+        check_indices(&mesh);
+        assert_eq!(mesh.0.len(), 2 * 7 + 2 * 8);
+        assert_eq!(mesh.1.len(), 3 * (2 * 6 + 2 * 6));
+        check_outward(&mesh);
+    }
+
+    #[test]
+    fn torus_faces_away_from_its_tube() {
+        let mesh = torus(0.35, 0.15, 8, 6, Colors::Position);
+        check_indices(&mesh);
+        assert_eq!(mesh.0.len(), 9 * 7);
+        assert_eq!(mesh.1.len(), 3 * 2 * 8 * 6);
+        for (normal, centre) in triangles(&mesh) {
+            let tube_centre = Vector3::new(centre.x, 0.0, centre.z).normalize() * 0.35;
+            assert!(normal.dot(centre - tube_centre) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,6 @@
+// the uniform of camera.rs, read by vertex shaders from set 0
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_proj;
+    // how much the camera zoomed in since the start, for shaders without view_proj
+    float scale;
+};
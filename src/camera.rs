@@ -0,0 +1,446 @@
+//! A camera looking at the scene through a perspective or orthographic projection
+//! and moved with the mouse by a `CameraController`. Vertex shaders read it from
+//! binding 0 of set 0 with `#include "camera.glsl"`.
+
+use cgmath::{ortho, perspective, Angle, Deg, InnerSpace, Matrix4, Point3, Rad, Vector2, Vector3};
+use serde::Deserialize;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferUsage, Device,
+    Queue,
+};
+
+/// The bind group vertex shaders read the camera from.
+pub(crate) const CAMERA_GROUP: u32 = 0;
+
+// cgmath projects depth to -1..1 like OpenGL, wgpu expects 0..1
+#[rustfmt::skip]
+const OPENGL_TO_WGPU: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// how much one line of scrolling scales the view
+const ZOOM_STEP: f32 = 0.9;
+// how far zooming goes either way, keeping the starting perspective view between
+// the near and far planes
+const MIN_SCALE: f32 = 0.1;
+const MAX_SCALE: f32 = 20.0;
+// how far one line of scrolling moves the fly camera
+const FLY_STEP: f32 = 0.1;
+// radians per second with the cursor at the window edge
+const TURN_SPEED: f32 = 1.5;
+// views per second with the cursor at the window edge
+const PAN_SPEED: f32 = 0.5;
+// keeps the up vector away from the view direction
+const MAX_PITCH: Deg<f32> = Deg(80.0);
+
+#[derive(Clone, Copy)]
+pub(crate) enum Projection {
+    Perspective {
+        fovy: Deg<f32>,
+        znear: f32,
+        zfar: f32,
+    },
+    // `height` world units fill the window vertically
+    Orthographic {
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl Projection {
+    pub(crate) fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let projection = match *self {
+            Projection::Perspective { fovy, znear, zfar } => perspective(fovy, aspect, znear, zfar),
+            Projection::Orthographic {
+                height,
+                znear,
+                zfar,
+            } => {
+                let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+                ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    znear,
+                    zfar,
+                )
+            }
+        };
+        OPENGL_TO_WGPU * projection
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Camera {
+    pub(crate) eye: Point3<f32>,
+    pub(crate) target: Point3<f32>,
+    pub(crate) up: Vector3<f32>,
+    pub(crate) projection: Projection,
+    // how much `zoom` magnified the view so far
+    pub(crate) scale: f32,
+}
+
+impl Camera {
+    /// Looks at the origin from +z, where the 2 units around it just fit the window.
+    pub(crate) fn perspective() -> Self {
+        Self {
+            eye: Point3::new(0.0, 0.0, 2.4),
+            target: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_y(),
+            projection: Projection::Perspective {
+                fovy: Deg(45.0),
+                znear: 0.1,
+                zfar: 100.0,
+            },
+            scale: 1.0,
+        }
+    }
+
+    /// Looks at the origin from +z, showing the same as clip space without a camera.
+    pub(crate) fn orthographic() -> Self {
+        Self {
+            projection: Projection::Orthographic {
+                height: 2.0,
+                znear: 0.1,
+                zfar: 100.0,
+            },
+            ..Self::perspective()
+        }
+    }
+
+    pub(crate) fn view_proj(&self, aspect: f32) -> Matrix4<f32> {
+        self.projection.matrix(aspect) * Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    /// How many world units fill the window vertically at the target.
+    pub(crate) fn view_height(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective { fovy, .. } => {
+                2.0 * (self.eye - self.target).magnitude() * (fovy / 2.0).tan()
+            }
+            Projection::Orthographic { height, .. } => height,
+        }
+    }
+
+    /// Scales the view around the target, zooming in below 1: the eye moves for a
+    /// perspective projection, the size of an orthographic one changes. Stops at
+    /// a magnification of `MIN_SCALE` and `MAX_SCALE`.
+    pub(crate) fn zoom(&mut self, factor: f32) {
+        let scale = (self.scale / factor).clamp(MIN_SCALE, MAX_SCALE);
+        let factor = self.scale / scale;
+        self.scale = scale;
+        match &mut self.projection {
+            Projection::Perspective { .. } => {
+                self.eye = self.target + (self.eye - self.target) * factor;
+            }
+            Projection::Orthographic { height, .. } => *height *= factor,
+        }
+    }
+}
+
+/// The mouse state controllers react to.
+pub(crate) struct CameraInput {
+    // -1..1 across the window with y up, None while the cursor is outside
+    pub(crate) cursor: Option<Vector2<f32>>,
+    // lines scrolled since the last update, positive away from the user
+    pub(crate) scroll: f32,
+    // seconds since the last update
+    pub(crate) dt: f32,
+}
+
+/// Turns mouse input into camera movement.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CameraController {
+    /// Circles the target: the cursor picks the angle around and above it,
+    /// scrolling zooms.
+    #[default]
+    Orbit,
+    /// Turns towards the cursor while it is away from the middle of the window,
+    /// scrolling moves forward and back.
+    Fly,
+    /// Slides across the xy plane while the cursor is near the window edges,
+    /// scrolling zooms.
+    Pan,
+}
+
+// 0 inside the dead zone, rising to 1 at the window edge
+fn past_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    ((value.abs() - dead_zone).max(0.0) / (1.0 - dead_zone)).copysign(value)
+}
+
+// yaw 0 looks along -z, positive yaw turns right and positive pitch up
+fn direction(yaw: Rad<f32>, pitch: Rad<f32>) -> Vector3<f32> {
+    Vector3::new(
+        yaw.sin() * pitch.cos(),
+        pitch.sin(),
+        -yaw.cos() * pitch.cos(),
+    )
+}
+
+impl CameraController {
+    pub(crate) fn update(self, camera: &mut Camera, input: &CameraInput) {
+        match self {
+            CameraController::Orbit => {
+                if let Some(cursor) = input.cursor {
+                    let yaw = Rad::from(Deg(180.0)) * cursor.x;
+                    let pitch = Rad::from(MAX_PITCH) * cursor.y;
+                    let distance = (camera.eye - camera.target).magnitude();
+                    // the eye sits opposite to where it looks
+                    camera.eye = camera.target - direction(yaw, pitch) * distance;
+                }
+                camera.zoom(ZOOM_STEP.powf(input.scroll));
+            }
+            CameraController::Fly => {
+                let forward = (camera.target - camera.eye).normalize();
+                let mut yaw = Rad(forward.x.atan2(-forward.z));
+                let mut pitch = Rad(forward.y.asin());
+                if let Some(cursor) = input.cursor {
+                    let turn = TURN_SPEED * input.dt;
+                    yaw += Rad(past_dead_zone(cursor.x, 0.2) * turn);
+                    pitch += Rad(past_dead_zone(cursor.y, 0.2) * turn);
+                    pitch.0 = pitch
+                        .0
+                        .clamp(-Rad::from(MAX_PITCH).0, Rad::from(MAX_PITCH).0);
+                }
+                let forward = direction(yaw, pitch);
+                camera.eye += forward * FLY_STEP * input.scroll;
+                camera.target = camera.eye + forward;
+            }
+            CameraController::Pan => {
+                if let Some(cursor) = input.cursor {
+                    let speed = camera.view_height() * PAN_SPEED * input.dt;
+                    let offset = Vector3::new(
+                        past_dead_zone(cursor.x, 0.8),
+                        past_dead_zone(cursor.y, 0.8),
+                        0.0,
+                    ) * speed;
+                    camera.eye += offset;
+                    camera.target += offset;
+                }
+                camera.zoom(ZOOM_STEP.powf(input.scroll));
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    scale: f32,
+    // uniform blocks are padded to 16 bytes
+    _padding: [f32; 3],
+}
+
+impl CameraUniform {
+    fn new(camera: &Camera, aspect: f32) -> Self {
+        Self {
+            view_proj: camera.view_proj(aspect).into(),
+            scale: camera.scale,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// A camera with its controller and the uniform buffer shaders read it from.
+pub(crate) struct CameraBinding {
+    pub(crate) camera: Camera,
+    pub(crate) controller: CameraController,
+    buffer: Buffer,
+    pub(crate) bind_group: BindGroup,
+}
+
+impl CameraBinding {
+    /// `layout` is the camera group of the vertex shader.
+    pub(crate) fn new(
+        device: &Device,
+        layout: &BindGroupLayout,
+        camera: Camera,
+        controller: CameraController,
+        aspect: f32,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("camera buffer"),
+            contents: bytemuck::bytes_of(&CameraUniform::new(&camera, aspect)),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("camera bind group"),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            camera,
+            controller,
+            buffer,
+            bind_group,
+        }
+    }
+
+    /// Moves the camera and uploads it for a target of `aspect` (width / height).
+    pub(crate) fn update(&mut self, queue: &Queue, input: &CameraInput, aspect: f32) {
+        self.controller.update(&mut self.camera, input);
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&CameraUniform::new(&self.camera, aspect)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::assert_abs_diff_eq;
+
+    // clip space after the perspective divide
+    fn project(view_proj: Matrix4<f32>, point: Point3<f32>) -> Vector3<f32> {
+        let clip = view_proj * point.to_homogeneous();
+        clip.truncate() / clip.w
+    }
+
+    fn input(cursor: Option<(f32, f32)>, scroll: f32) -> CameraInput {
+        CameraInput {
+            cursor: cursor.map(|(x, y)| Vector2::new(x, y)),
+            scroll,
+            dt: 0.5,
+        }
+    }
+
+    #[test]
+    fn perspective_maps_the_depth_range_to_0_1() {
+        let camera = Camera::perspective();
+        let view_proj = camera.view_proj(1.5);
+        assert_abs_diff_eq!(
+            project(view_proj, camera.target).truncate(),
+            Vector2::new(0.0, 0.0)
+        );
+        // znear and zfar in front of the eye
+        let near = project(view_proj, Point3::new(0.0, 0.0, 2.3));
+        let far = project(view_proj, Point3::new(0.0, 0.0, -97.6));
+        assert_abs_diff_eq!(near.z, 0.0, epsilon = 1e-4);
+        assert_abs_diff_eq!(far.z, 1.0, epsilon = 1e-4);
+        // the 2 units around the origin just fit vertically
+        let top = project(view_proj, Point3::new(0.0, camera.view_height() / 2.0, 0.0));
+        assert_abs_diff_eq!(top.y, 1.0, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn orthographic_maps_its_extents_to_the_window_edges() {
+        let camera = Camera::orthographic();
+        let view_proj = camera.view_proj(2.0);
+        // 2 units high, twice as wide
+        let corner = project(view_proj, Point3::new(2.0, 1.0, 0.0));
+        assert_abs_diff_eq!(corner.truncate(), Vector2::new(1.0, 1.0), epsilon = 1e-6);
+        let corner = project(view_proj, Point3::new(-2.0, -1.0, 0.0));
+        assert_abs_diff_eq!(corner.truncate(), Vector2::new(-1.0, -1.0), epsilon = 1e-6);
+        // the same everywhere between the planes
+        let deep = project(view_proj, Point3::new(2.0, 1.0, -50.0));
+        assert_abs_diff_eq!(deep.truncate(), Vector2::new(1.0, 1.0), epsilon = 1e-6);
+        assert!(deep.z > 0.0 && deep.z < 1.0);
+        assert_eq!(camera.view_height(), 2.0);
+    }
+
+    #[test]
+    fn orbit_circles_the_target_at_the_same_distance() {
+        let mut camera = Camera::perspective();
+        let distance = (camera.eye - camera.target).magnitude();
+        CameraController::Orbit.update(&mut camera, &input(Some((0.0, 0.0)), 0.0));
+        assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, distance), epsilon = 1e-6);
+        for cursor in [(0.5, 0.0), (-1.0, 0.3), (0.2, -1.0), (1.0, 1.0)] {
+            CameraController::Orbit.update(&mut camera, &input(Some(cursor), 0.0));
+            let offset = camera.eye - camera.target;
+            assert_abs_diff_eq!(offset.magnitude(), distance, epsilon = 1e-5);
+            // never straight above or below, where the up vector breaks down
+            assert!(offset.normalize().y.abs() <= Rad::from(MAX_PITCH).sin() + 1e-6);
+        }
+        // half way to the right edge is a quarter turn
+        CameraController::Orbit.update(&mut camera, &input(Some((0.5, 0.0)), 0.0));
+        assert_abs_diff_eq!(camera.eye, Point3::new(-distance, 0.0, 0.0), epsilon = 1e-5);
+        // without the cursor only scrolling does anything
+        let eye = camera.eye;
+        CameraController::Orbit.update(&mut camera, &input(None, 0.0));
+        assert_eq!(camera.eye, eye);
+    }
+
+    #[test]
+    fn fly_turns_outside_the_dead_zone_and_scrolls_forward() {
+        let mut camera = Camera::perspective();
+        CameraController::Fly.update(&mut camera, &input(Some((0.1, -0.1)), 0.0));
+        assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, 2.4));
+        assert_abs_diff_eq!(camera.target, Point3::new(0.0, 0.0, 1.4), epsilon = 1e-6);
+        CameraController::Fly.update(&mut camera, &input(None, 10.0));
+        assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, 1.4), epsilon = 1e-6);
+        // at the right edge it turns right at full speed
+        CameraController::Fly.update(&mut camera, &input(Some((1.0, 0.0)), 0.0));
+        let forward = camera.target - camera.eye;
+        assert_abs_diff_eq!(forward.magnitude(), 1.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(
+            forward.x.atan2(-forward.z),
+            TURN_SPEED * 0.5,
+            epsilon = 1e-5
+        );
+        // looking up stops short of straight up
+        for _ in 0..10 {
+            CameraController::Fly.update(&mut camera, &input(Some((0.0, 1.0)), 0.0));
+        }
+        let forward = camera.target - camera.eye;
+        assert_abs_diff_eq!(forward.y.asin(), Rad::from(MAX_PITCH).0, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn pan_slides_eye_and_target_near_the_edges() {
+        let mut camera = Camera::orthographic();
+        CameraController::Pan.update(&mut camera, &input(Some((0.5, -0.7)), 0.0));
+        assert_eq!(camera.eye, Point3::new(0.0, 0.0, 2.4));
+        CameraController::Pan.update(&mut camera, &input(Some((1.0, -1.0)), 0.0));
+        // a view height per second, for half a second
+        let step = camera.view_height() * PAN_SPEED * 0.5;
+        assert_abs_diff_eq!(camera.eye, Point3::new(step, -step, 2.4), epsilon = 1e-6);
+        assert_abs_diff_eq!(camera.target, Point3::new(step, -step, 0.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn zoom_scales_the_view_within_its_limits() {
+        let mut camera = Camera::perspective();
+        camera.zoom(0.5);
+        assert_eq!(camera.scale, 2.0);
+        assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, 1.2));
+        let mut ortho = Camera::orthographic();
+        ortho.zoom(0.5);
+        assert_eq!((ortho.scale, ortho.view_height()), (2.0, 1.0));
+        assert_eq!(ortho.eye, Point3::new(0.0, 0.0, 2.4));
+        // scrolling a long way in or out stops at the limits
+        camera.zoom(ZOOM_STEP.powf(1000.0));
+        assert_abs_diff_eq!(camera.scale, MAX_SCALE);
+        assert_abs_diff_eq!(camera.eye.z, 2.4 / MAX_SCALE, epsilon = 1e-6);
+        camera.zoom(ZOOM_STEP.powf(-1000.0));
+        assert_abs_diff_eq!(camera.scale, MIN_SCALE);
+        assert_abs_diff_eq!(camera.eye.z, 2.4 / MIN_SCALE, epsilon = 1e-4);
+        ortho.zoom(1e6);
+        assert_abs_diff_eq!(ortho.view_height(), 2.0 / MIN_SCALE, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn uniform_carries_the_view_and_the_scale() {
+        let mut camera = Camera::orthographic();
+        camera.zoom(0.25);
+        let uniform = CameraUniform::new(&camera, 1.5);
+        assert_eq!(uniform.scale, 4.0);
+        let view_proj: [[f32; 4]; 4] = camera.view_proj(1.5).into();
+        assert_eq!(uniform.view_proj, view_proj);
+        // view_proj, then scale at offset 64, padded to a multiple of 16 bytes
+        assert_eq!(std::mem::size_of::<CameraUniform>(), 80);
+        assert_eq!(std::mem::offset_of!(CameraUniform, scale), 64);
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{bail, Result};
+use futures::executor::block_on;
+use image::RgbaImage;
+use std::num::NonZeroU32;
+use wgpu::{
+    BufferAddress, BufferDescriptor, BufferUsage, CommandEncoder, Device, Extent3d,
+    ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Maintain, MapMode, Origin3d, Queue,
+    Texture, TextureFormat, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+// rows copied out of a texture have to start on a 256 byte boundary
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let unpadded = width * BYTES_PER_PIXEL;
+    let align = COPY_BYTES_PER_ROW_ALIGNMENT;
+    unpadded.div_ceil(align) * align
+}
+
+/// Copies `texture` into a mappable buffer and reads it back as an RGBA image.
+/// `encoder` may already hold the commands that render into `texture`.
+pub(crate) fn texture_to_image(
+    device: &Device,
+    queue: &Queue,
+    mut encoder: CommandEncoder,
+    texture: &Texture,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage> {
+    let swizzle = match format {
+        // sRGB texels are already encoded the way PNG expects, so no conversion is needed
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+        _ => bail!("capturing {:?} textures is not supported", format),
+    };
+    let padded_row = padded_bytes_per_row(width);
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("capture buffer"),
+        size: (padded_row * height) as BufferAddress,
+        usage: BufferUsage::COPY_DST | BufferUsage::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+        },
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_row),
+                rows_per_image: NonZeroU32::new(height),
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(MapMode::Read);
+    device.poll(Maintain::Wait);
+    block_on(mapping)?;
+    let mut pixels = Vec::with_capacity((width * height * BYTES_PER_PIXEL) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_row as usize) {
+            pixels.extend_from_slice(&row[..(width * BYTES_PER_PIXEL) as usize]);
+        }
+    }
+    buffer.unmap();
+    if swizzle {
+        for pixel in pixels.chunks_mut(BYTES_PER_PIXEL as usize) {
+            pixel.swap(0, 2);
+        }
+    }
+    match RgbaImage::from_raw(width, height, pixels) {
+        Some(image) => Ok(image),
+        None => bail!("captured data does not match {}x{}", width, height),
+    }
+}
@@ -5,7 +5,9 @@ layout(location = 1) in vec3 a_color;
 
 layout(location = 0) out vec3 v_color;
 
+#include "camera.glsl"
+
 void main() {
     v_color = a_color;
-    gl_Position = vec4(a_position, 1.0);
+    gl_Position = vec4(a_position.xy * scale, a_position.z, 1.0);
 }
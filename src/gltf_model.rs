@@ -0,0 +1,527 @@
+//! glTF 2.0 and GLB files: meshes, materials, textures and the node hierarchy of
+//! their default scene.
+
+use crate::{
+    buffers::ModelVertex,
+    mesh_tools,
+    model::{Model, ModelMaterial, ModelMesh},
+    texture::{ColorSpace, TextureData},
+};
+use anyhow::{bail, ensure, Context, Result};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3};
+use gltf::{image::Format, mesh::Mode};
+use image::{DynamicImage, ImageBuffer};
+use std::path::Path;
+
+pub(crate) struct GltfNode {
+    pub(crate) name: String,
+    pub(crate) mesh: Option<usize>,
+    pub(crate) children: Vec<usize>,
+    // relative to the parent node
+    pub(crate) transform: Matrix4<f32>,
+}
+
+/// An imported glTF file. Every primitive is a mesh of `model` in the space of
+/// its glTF mesh; the nodes place the glTF meshes in the scene.
+pub(crate) struct GltfScene {
+    pub(crate) model: Model,
+    // the `model` meshes making up each glTF mesh
+    pub(crate) meshes: Vec<Vec<usize>>,
+    pub(crate) nodes: Vec<GltfNode>,
+    // nodes of the default scene
+    pub(crate) roots: Vec<usize>,
+}
+
+fn texture_data(data: &gltf::image::Data, label: &str) -> Result<TextureData> {
+    let (width, height, pixels) = (data.width, data.height, data.pixels.clone());
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        }
+        format => bail!("{}: unsupported pixel format {:?}", label, format),
+    }
+    .with_context(|| format!("{}: truncated image", label))?;
+    Ok(TextureData::from_image(&image, label, ColorSpace::Srgb))
+}
+
+fn normalized(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+impl GltfScene {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let import = gltf::import(path).with_context(|| format!("invalid glTF file {:?}", path))?;
+        Self::from_import(import, &path.display().to_string())
+    }
+
+    // `label` names the file in errors
+    fn from_import(
+        (document, buffers, images): (
+            gltf::Document,
+            Vec<gltf::buffer::Data>,
+            Vec<gltf::image::Data>,
+        ),
+        label: &str,
+    ) -> Result<Self> {
+        let materials = document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                let [r, g, b, _] = pbr.base_color_factor();
+                let diffuse_texture = match pbr.base_color_texture() {
+                    Some(info) => {
+                        let image = info.texture().source().index();
+                        let label = format!("{} image {}", label, image);
+                        Some(texture_data(&images[image], &label)?)
+                    }
+                    None => None,
+                };
+                Ok(ModelMaterial {
+                    name: material.name().unwrap_or_default().to_string(),
+                    diffuse: [r, g, b],
+                    diffuse_texture,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut primitives = Vec::new();
+        let mut meshes = Vec::new();
+        for mesh in document.meshes() {
+            let name = mesh
+                .name()
+                .map_or_else(|| format!("mesh {}", mesh.index()), str::to_string);
+            let mut mesh_primitives = Vec::new();
+            for (number, primitive) in mesh.primitives().enumerate() {
+                let name = format!("{} primitive {}", name, number);
+                ensure!(
+                    matches!(primitive.mode(), Mode::Triangles),
+                    "{}: only triangle lists are supported",
+                    name
+                );
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions: Vec<[f32; 3]> = reader
+                    .read_positions()
+                    .with_context(|| format!("{}: no positions", name))?
+                    .collect();
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices
+                        .into_u32()
+                        .map(|index| {
+                            ensure!(
+                                (index as usize) < positions.len(),
+                                "{}: index {} out of range",
+                                name,
+                                index
+                            );
+                            Ok(index)
+                        })
+                        .collect::<Result<_>>()?,
+                    None => (0..positions.len() as u32).collect(),
+                };
+                let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+                // tangents given for normals that have to be computed don't fit them
+                let tangents: Option<Vec<[f32; 4]>> = match normals {
+                    Some(_) => reader.read_tangents().map(Iterator::collect),
+                    None => None,
+                };
+                let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+                    Some(tex_coords) => tex_coords.into_f32().collect(),
+                    None => vec![[0.0; 2]; positions.len()],
+                };
+                let colors: Vec<[f32; 3]> = match reader.read_colors(0) {
+                    Some(colors) => colors.into_rgb_f32().collect(),
+                    None => vec![[1.0; 3]; positions.len()],
+                };
+                ensure!(
+                    normals
+                        .as_ref()
+                        .is_none_or(|normals| normals.len() == positions.len())
+                        && tangents
+                            .as_ref()
+                            .is_none_or(|tangents| tangents.len() == positions.len())
+                        && tex_coords.len() == positions.len()
+                        && colors.len() == positions.len(),
+                    "{}: attributes of different lengths",
+                    name
+                );
+                let material = primitive.material().index();
+                let diffuse = material.map_or([1.0; 3], |material| materials[material].diffuse);
+                let vertices: Vec<_> = (0..positions.len())
+                    .map(|i| ModelVertex {
+                        position: positions[i],
+                        normal: normals.as_ref().map_or([0.0; 3], |normals| normals[i]),
+                        tex_coords: tex_coords[i],
+                        color: [0, 1, 2].map(|c| colors[i][c] * diffuse[c]),
+                        tangent: tangents.as_ref().map_or([0.0; 4], |tangents| tangents[i]),
+                    })
+                    .collect();
+                // the glTF spec asks for flat normals and MikkTSpace tangents
+                let (vertices, indices) = match (&normals, &tangents) {
+                    (None, _) => {
+                        let (vertices, indices) = mesh_tools::flat_normals(&vertices, &indices);
+                        mesh_tools::tangents(&vertices, &indices)
+                    }
+                    (Some(_), None) => mesh_tools::tangents(&vertices, &indices),
+                    (Some(_), Some(_)) => (vertices, indices),
+                };
+                mesh_primitives.push(primitives.len());
+                primitives.push(ModelMesh {
+                    name,
+                    vertices,
+                    indices,
+                    material,
+                });
+            }
+            meshes.push(mesh_primitives);
+        }
+        let nodes = document
+            .nodes()
+            .map(|node| GltfNode {
+                name: node
+                    .name()
+                    .map_or_else(|| format!("node {}", node.index()), str::to_string),
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                children: node.children().map(|child| child.index()).collect(),
+                transform: Matrix4::from(node.transform().matrix()),
+            })
+            .collect::<Vec<_>>();
+        // without scenes every node that isn't a child is a root
+        let roots = match document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len())
+                .filter(|&index| !nodes.iter().any(|node| node.children.contains(&index)))
+                .collect(),
+        };
+        Ok(Self {
+            model: Model {
+                meshes: primitives,
+                materials,
+            },
+            meshes,
+            nodes,
+            roots,
+        })
+    }
+
+    /// Places a copy of the primitives of every node with a mesh in scene space.
+    pub(crate) fn bake(self) -> Model {
+        let mut meshes = Vec::new();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack: Vec<_> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&root| (root, Matrix4::identity()))
+            .collect();
+        while let Some((index, parent)) = stack.pop() {
+            // glTF nodes have one parent at most, this only guards against cycles
+            if std::mem::replace(&mut visited[index], true) {
+                continue;
+            }
+            let node = &self.nodes[index];
+            let world: Matrix4<f32> = parent * node.transform;
+            stack.extend(node.children.iter().rev().map(|&child| (child, world)));
+            // the inverse transpose keeps normals perpendicular under non-uniform scale
+            let normal_matrix = world.invert().unwrap_or(world).transpose();
+            let normal_matrix = Matrix3::from_cols(
+                normal_matrix.x.truncate(),
+                normal_matrix.y.truncate(),
+                normal_matrix.z.truncate(),
+            );
+            // tangents lie in the surface, so they move with it
+            let tangent_matrix =
+                Matrix3::from_cols(world.x.truncate(), world.y.truncate(), world.z.truncate());
+            // a mirroring transform turns front faces around, which swapping two
+            // corners of every triangle undoes, and flips the bitangent
+            let mirrored = world.determinant() < 0.0;
+            for &primitive in node.mesh.iter().flat_map(|&mesh| &self.meshes[mesh]) {
+                let source = &self.model.meshes[primitive];
+                meshes.push(ModelMesh {
+                    name: format!("{}/{}", node.name, source.name),
+                    vertices: source
+                        .vertices
+                        .iter()
+                        .map(|vertex| ModelVertex {
+                            position: (world * Vector3::from(vertex.position).extend(1.0))
+                                .truncate()
+                                .into(),
+                            normal: normalized(normal_matrix * Vector3::from(vertex.normal)).into(),
+                            tangent: {
+                                let [x, y, z, w] = vertex.tangent;
+                                let xyz = normalized(tangent_matrix * Vector3::new(x, y, z));
+                                xyz.extend(if mirrored { -w } else { w }).into()
+                            },
+                            ..*vertex
+                        })
+                        .collect(),
+                    indices: source
+                        .indices
+                        .chunks(3)
+                        .flat_map(|triangle| match (mirrored, triangle) {
+                            (true, &[a, b, c]) => vec![a, c, b],
+                            _ => triangle.to_vec(),
+                        })
+                        .collect(),
+                    material: source.material,
+                });
+            }
+        }
+        Model {
+            meshes,
+            materials: self.model.materials,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> ModelMesh {
+        let vertex = |position| ModelVertex {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            tex_coords: [0.0; 2],
+            color: [1.0; 3],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+        };
+        ModelMesh {
+            name: "triangle".to_string(),
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0]),
+                vertex([1.0, 0.0, 0.0]),
+                vertex([0.0, 1.0, 0.0]),
+            ],
+            indices: vec![0, 1, 2],
+            material: None,
+        }
+    }
+
+    fn node(name: &str, children: Vec<usize>, transform: Matrix4<f32>) -> GltfNode {
+        GltfNode {
+            name: name.to_string(),
+            mesh: Some(0),
+            children,
+            transform,
+        }
+    }
+
+    // a GLB of `json` with a triangle's positions, normals, texture coordinates
+    // and indices in its binary chunk
+    fn glb(json: &str) -> Vec<u8> {
+        let mut bin = Vec::new();
+        for value in [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0f32] {
+            bin.extend_from_slice(&value.to_le_bytes());
+        }
+        for _ in 0..3 {
+            for value in [0.0, 0.0, 1.0f32] {
+                bin.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        for value in [0.0, 1.0, 1.0, 1.0, 0.0, 0.0f32] {
+            bin.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2, 0] {
+            bin.extend_from_slice(&index.to_le_bytes());
+        }
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&(12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"JSON");
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"BIN\0");
+        bytes.extend_from_slice(&bin);
+        bytes
+    }
+
+    // one mesh with two primitives sharing the positions: the first with normals,
+    // texture coordinates, indices and a material, the second with positions only;
+    // the mesh sits scaled under a translated parent
+    const GLB_JSON: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 104 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 72, "byteLength": 24 },
+            { "buffer": 0, "byteOffset": 96, "byteLength": 6 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+              "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2" },
+            { "bufferView": 3, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "materials": [{
+            "name": "orange",
+            "pbrMetallicRoughness": { "baseColorFactor": [1, 0.5, 0.25, 1] }
+        }],
+        "meshes": [{
+            "name": "tri",
+            "primitives": [
+                { "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                  "indices": 3, "material": 0 },
+                { "attributes": { "POSITION": 0 } }
+            ]
+        }],
+        "nodes": [
+            { "name": "parent", "translation": [1, 0, 0], "children": [1] },
+            { "name": "child", "mesh": 0, "scale": [2, 2, 2] }
+        ],
+        "scenes": [{ "nodes": [0] }],
+        "scene": 0
+    }"#;
+
+    fn import(json: &str) -> Result<GltfScene> {
+        GltfScene::from_import(gltf::import_slice(glb(json))?, "test.glb")
+    }
+
+    #[test]
+    fn loads_primitives_materials_and_nodes_of_a_glb() {
+        let scene = import(GLB_JSON).unwrap();
+        assert_eq!(scene.meshes, vec![vec![0, 1]]);
+        assert_eq!(scene.roots, vec![0]);
+        assert_eq!(scene.nodes[0].children, vec![1]);
+        assert_eq!(scene.nodes[1].mesh, Some(0));
+        assert_eq!(
+            scene.nodes[0].transform,
+            Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0))
+        );
+        assert_eq!(scene.model.materials[0].name, "orange");
+        let [full, bare] = [0, 1].map(|i| &scene.model.meshes[i]);
+        assert_eq!(full.name, "tri primitive 0");
+        assert_eq!(full.material, Some(0));
+        assert_eq!(full.indices, vec![0, 1, 2]);
+        assert_eq!(full.vertices[2].tex_coords, [0.0, 0.0]);
+        for vertex in &full.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+            // no vertex colors, so the material's
+            assert_eq!(vertex.color, [1.0, 0.5, 0.25]);
+            // generated along increasing u, with v running down
+            let [x, y, z, w] = vertex.tangent;
+            assert!((x - 1.0).abs() < 1e-5 && y.abs() < 1e-5 && z.abs() < 1e-5);
+            assert_eq!(w, -1.0);
+        }
+        // without normals the faces are flat, without texture coordinates at 0
+        assert_eq!(bare.material, None);
+        assert_eq!(bare.vertices.len(), 3);
+        for vertex in &bare.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+            assert_eq!(vertex.tex_coords, [0.0; 2]);
+            assert_eq!(vertex.color, [1.0; 3]);
+        }
+        // the child is scaled, then moved with its parent
+        let model = scene.bake();
+        let corners = model.meshes[0]
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            corners,
+            vec![[1.0, 0.0, 0.0], [3.0, 0.0, 0.0], [1.0, 2.0, 0.0]]
+        );
+    }
+
+    #[test]
+    fn rejects_primitives_other_than_triangle_lists() {
+        let lines = GLB_JSON.replace(r#""material": 0 }"#, r#""material": 0, "mode": 1 }"#);
+        let error = import(&lines).err().unwrap();
+        assert!(
+            format!("{:?}", error).contains("only triangle lists"),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_indices_past_the_positions() {
+        // two positions left for the indices 0, 1 and 2
+        let short = GLB_JSON.replacen(r#""count": 3"#, r#""count": 2"#, 1);
+        let error = import(&short).err().unwrap();
+        assert!(
+            format!("{:?}", error).contains("index 2 out of range"),
+            "{:?}",
+            error
+        );
+    }
+
+    // the normal of the first triangle from its winding
+    fn winding_normal(mesh: &ModelMesh) -> Vector3<f32> {
+        let [a, b, c] =
+            [0, 1, 2].map(|i| Vector3::from(mesh.vertices[mesh.indices[i] as usize].position));
+        (b - a).cross(c - a).normalize()
+    }
+
+    #[test]
+    fn bakes_nodes_in_scene_space() {
+        let scene = GltfScene {
+            model: Model {
+                meshes: vec![triangle()],
+                materials: Vec::new(),
+            },
+            meshes: vec![vec![0]],
+            nodes: vec![
+                node(
+                    "parent",
+                    vec![1],
+                    Matrix4::from_translation(Vector3::unit_x()),
+                ),
+                node(
+                    "child",
+                    Vec::new(),
+                    Matrix4::from_nonuniform_scale(1.0, 2.0, 1.0),
+                ),
+            ],
+            roots: vec![0],
+        };
+        let model = scene.bake();
+        let names = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["parent/triangle", "child/triangle"]);
+        assert_eq!(model.meshes[1].vertices[1].position, [2.0, 0.0, 0.0]);
+        assert_eq!(model.meshes[1].vertices[2].position, [1.0, 2.0, 0.0]);
+        assert_eq!(model.meshes[1].indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn reverses_the_winding_of_mirrored_nodes() {
+        let scene = GltfScene {
+            model: Model {
+                meshes: vec![triangle()],
+                materials: Vec::new(),
+            },
+            meshes: vec![vec![0]],
+            nodes: vec![node(
+                "mirrored",
+                Vec::new(),
+                Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0),
+            )],
+            roots: vec![0],
+        };
+        let mesh = &scene.bake().meshes[0];
+        assert_eq!(mesh.indices, vec![0, 2, 1]);
+        // front faces still wind counter-clockwise around their normals
+        let normal = Vector3::from(mesh.vertices[0].normal);
+        assert!(winding_normal(mesh).dot(normal) > 0.99);
+        // the texture is mirrored with the mesh, so its bitangent flips
+        assert_eq!(mesh.vertices[0].tangent, [-1.0, 0.0, 0.0, -1.0]);
+    }
+}
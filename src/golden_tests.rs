@@ -0,0 +1,102 @@
+//! Renders every `SpecificRender` offscreen and compares it against the reference
+//! images in `tests/golden`. Run with `GOLDEN_BLESS=1` to (re)write the references
+//! after an intentional change; failures leave the actual and diff images in
+//! `target/golden`. Without any adapter, not even a software rasterizer like
+//! lavapipe or llvmpipe, the test fails rather than passing unchecked.
+
+use crate::{scene::Scenes, swapchain::State};
+use futures::executor::block_on;
+use image::{Rgba, RgbaImage};
+use std::{env, fs, path::PathBuf};
+use winit::dpi::PhysicalSize;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+const TOLERANCE: u8 = 2;
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+// returns the number of pixels outside the tolerance and an image highlighting them
+fn diff_images(expected: &RgbaImage, actual: &RgbaImage) -> (usize, RgbaImage) {
+    let mut mismatches = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let e = expected.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+        let differs =
+            e.0.iter()
+                .zip(a.0.iter())
+                .any(|(e, a)| (*e as i16 - *a as i16).abs() > TOLERANCE as i16);
+        if differs {
+            mismatches += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([a[0] / 4, a[1] / 4, a[2] / 4, 255])
+        }
+    });
+    (mismatches, diff)
+}
+
+#[test]
+fn specific_renders_match_golden_images() {
+    let scenes = Scenes::load(None).unwrap();
+    let mut state = block_on(State::new_headless(
+        PhysicalSize::new(WIDTH, HEIGHT),
+        &scenes,
+        1,
+    ))
+    .expect("rendering the golden images needs an adapter, a software one will do");
+    // animated renders must not depend on how long the previous ones took
+    state.set_fixed_timestep(Some(crate::FRAME_TIME));
+    let bless = env::var_os("GOLDEN_BLESS").is_some();
+    let golden_dir = manifest_dir().join("tests").join("golden");
+    let output_dir = manifest_dir().join("target").join("golden");
+    let mut failures = Vec::new();
+    for _ in 0..state.render_count() {
+        state.update();
+        state.render().unwrap();
+        let name = state.render_name().to_string();
+        let actual = state.capture_frame().unwrap();
+        let reference_path = golden_dir.join(format!("{}.png", name));
+        if bless {
+            fs::create_dir_all(&golden_dir).unwrap();
+            actual.save(&reference_path).unwrap();
+            state.next_render();
+            continue;
+        }
+        let expected = match image::open(&reference_path) {
+            Ok(expected) => expected.to_rgba8(),
+            Err(e) => {
+                failures.push(format!("{}: no reference image ({})", name, e));
+                state.next_render();
+                continue;
+            }
+        };
+        if expected.dimensions() != actual.dimensions() {
+            failures.push(format!(
+                "{}: expected {:?}, rendered {:?}",
+                name,
+                expected.dimensions(),
+                actual.dimensions()
+            ));
+        } else {
+            let (mismatches, diff) = diff_images(&expected, &actual);
+            if mismatches > 0 {
+                fs::create_dir_all(&output_dir).unwrap();
+                actual
+                    .save(output_dir.join(format!("{}.actual.png", name)))
+                    .unwrap();
+                diff.save(output_dir.join(format!("{}.diff.png", name)))
+                    .unwrap();
+                failures.push(format!("{}: {} pixels differ", name, mismatches));
+            }
+        }
+        state.next_render();
+    }
+    assert!(
+        failures.is_empty(),
+        "golden images differ (rerun with GOLDEN_BLESS=1 if intended):\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,177 @@
+use crate::{
+    pipeline_creator::SpecificRender,
+    shader_compiler::{self, ShaderKind},
+    shaders::CompiledShader,
+};
+use anyhow::Result;
+use log::{error, info};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use wgpu::{
+    util::make_spirv, Device, ShaderFlags, ShaderModule, ShaderModuleDescriptor, TextureFormat,
+};
+
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+struct WatchedShader {
+    shader: &'static CompiledShader,
+    // the shader source followed by everything it includes
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl WatchedShader {
+    fn new(shader: &'static CompiledShader) -> Self {
+        let mut watched = Self {
+            shader,
+            files: Vec::new(),
+        };
+        let mut includes = Vec::new();
+        // a broken include only means it isn't watched until the next successful compile
+        let _ = shader_compiler::preprocess(
+            Path::new(shader.source),
+            Path::new(SHADER_DIR),
+            &mut includes,
+        );
+        watched.watch(includes);
+        watched
+    }
+
+    fn watch(&mut self, includes: Vec<PathBuf>) {
+        self.files = std::iter::once(PathBuf::from(self.shader.source))
+            .chain(includes)
+            .map(|path| {
+                let time = modified(&path);
+                (path, time)
+            })
+            .collect();
+    }
+
+    fn changed(&self) -> bool {
+        self.files
+            .iter()
+            .any(|(path, time)| modified(path) != *time)
+    }
+
+    fn compile(&mut self) -> Result<Vec<u8>> {
+        let src_path = Path::new(self.shader.source);
+        let mut includes = Vec::new();
+        let result = shader_compiler::preprocess(src_path, Path::new(SHADER_DIR), &mut includes)
+            .and_then(|src| {
+                let defines = self
+                    .shader
+                    .defines
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect();
+                shader_compiler::compile(&src, src_path, ShaderKind::from_path(src_path)?, &defines)
+            });
+        // remember the new timestamps even on failure so the error is logged only once
+        self.watch(includes);
+        result.map(|(spirv, _, _)| spirv)
+    }
+}
+
+/// Recompiles the shaders of every `SpecificRender` when their sources change and
+/// rebuilds the affected pipelines, keeping the old pipeline when anything fails.
+pub(crate) struct ShaderWatcher {
+    watched: Vec<WatchedShader>,
+    // latest successfully recompiled SPIR-V by shader name
+    reloaded: HashMap<&'static str, Vec<u8>>,
+    device_errors: Arc<Mutex<Vec<String>>>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub(crate) fn new(device: &Device, renders: &[SpecificRender]) -> Self {
+        let mut watched: Vec<WatchedShader> = Vec::new();
+        for render in renders {
+            for shader in [
+                render.pipeline_builder.vertex_shader(),
+                render.pipeline_builder.fragment_shader(),
+            ]
+            .iter()
+            {
+                if !watched.iter().any(|w| w.shader.name == shader.name) {
+                    watched.push(WatchedShader::new(shader));
+                }
+            }
+        }
+        // a failed pipeline is reported here instead of panicking the app
+        let device_errors = Arc::new(Mutex::new(Vec::new()));
+        let errors = device_errors.clone();
+        device.on_uncaptured_error(move |e| {
+            error!("{}", e);
+            errors.lock().unwrap().push(e.to_string());
+        });
+        Self {
+            watched,
+            reloaded: HashMap::new(),
+            device_errors,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn create_module(&self, device: &Device, shader: &CompiledShader) -> ShaderModule {
+        match self.reloaded.get(shader.name) {
+            Some(spirv) => device.create_shader_module(&ShaderModuleDescriptor {
+                label: Some(shader.name),
+                source: make_spirv(spirv),
+                flags: ShaderFlags::VALIDATION,
+            }),
+            None => shader.create_module(device),
+        }
+    }
+
+    pub(crate) fn poll(
+        &mut self,
+        device: &Device,
+        format: TextureFormat,
+        renders: &mut [SpecificRender],
+    ) {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+        let mut changed = Vec::new();
+        for watched in self.watched.iter_mut().filter(|w| w.changed()) {
+            match watched.compile() {
+                Ok(spirv) => {
+                    info!("recompiled {}", watched.shader.name);
+                    self.reloaded.insert(watched.shader.name, spirv);
+                    changed.push(watched.shader.name);
+                }
+                Err(e) => error!("keeping previous {}: {:?}", watched.shader.name, e),
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+        for render in renders.iter_mut().filter(|render| {
+            changed.contains(&render.pipeline_builder.vertex_shader().name)
+                || changed.contains(&render.pipeline_builder.fragment_shader().name)
+        }) {
+            self.device_errors.lock().unwrap().clear();
+            let vs_module = self.create_module(device, render.pipeline_builder.vertex_shader());
+            let fs_module = self.create_module(device, render.pipeline_builder.fragment_shader());
+            let pipeline = render
+                .pipeline_builder
+                .build_with_modules(device, format, &vs_module, &fs_module);
+            if self.device_errors.lock().unwrap().is_empty() {
+                render.render_pipeline = pipeline;
+                info!("rebuilt {} pipeline", render.name);
+            } else {
+                error!("keeping previous {} pipeline", render.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_color;
+// the model matrix by columns and the tint of each instance
+layout(location = 2) in vec4 a_model_0;
+layout(location = 3) in vec4 a_model_1;
+layout(location = 4) in vec4 a_model_2;
+layout(location = 5) in vec4 a_model_3;
+layout(location = 6) in vec3 a_tint;
+
+layout(location = 0) out vec3 v_color;
+
+#include "camera.glsl"
+
+void main() {
+    mat4 model = mat4(a_model_0, a_model_1, a_model_2, a_model_3);
+    v_color = a_color * a_tint;
+    gl_Position = view_proj * model * vec4(a_position, 1.0);
+}
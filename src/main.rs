@@ -1,21 +1,105 @@
+use anyhow::{Context, Result};
 use futures::executor::block_on;
+use log::info;
+use scene::Scenes;
 use simplelog::{Config, LevelFilter, SimpleLogger};
+use std::{path::PathBuf, time::Duration};
 use wgpu::{self, SwapChainError};
 use winit::{
+    dpi::PhysicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
+mod atlas;
 mod buffers;
+mod camera;
+mod capture;
+mod gltf_model;
+#[cfg(test)]
+mod golden_tests;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod mesh_tools;
+mod model;
+mod obj;
 mod pipeline_creator;
+mod scene;
+#[cfg(feature = "hot-reload")]
+mod shader_compiler;
+mod shaders;
 mod swapchain;
+mod texture;
+
+// the animation step of headless renders, which have no clock to follow
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+fn run_headless(scenes: &Scenes, sample_count: u32) -> Result<()> {
+    let mut state = block_on(swapchain::State::new_headless(
+        PhysicalSize::new(800, 600),
+        scenes,
+        sample_count,
+    ))?;
+    state.set_fixed_timestep(Some(FRAME_TIME));
+    for _ in 0..state.render_count() {
+        state.update();
+        state.render()?;
+        info!("rendered offscreen: {}", state.render_name());
+        state.next_render();
+    }
+    Ok(())
+}
 
 fn main() {
     let _ = SimpleLogger::init(LevelFilter::Debug, Config::default());
+    let args = std::env::args().collect::<Vec<_>>();
+    let scenes_path = args
+        .iter()
+        .position(|arg| arg == "--scenes")
+        .and_then(|index| args.get(index + 1))
+        .map(PathBuf::from);
+    let scenes = match Scenes::load(scenes_path.as_deref()) {
+        Ok(scenes) => scenes,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
+    // samples per pixel: 1 turns multisampling off, 4 is the only other count
+    let sample_count = match args.iter().position(|arg| arg == "--samples") {
+        Some(index) => match args
+            .get(index + 1)
+            .context("--samples needs a number")
+            .and_then(|count| {
+                count
+                    .parse::<u32>()
+                    .with_context(|| format!("invalid sample count {:?}", count))
+            }) {
+            Ok(count) => count,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        },
+        None => 1,
+    };
+    if args.iter().any(|arg| arg == "--headless") {
+        if let Err(e) = run_headless(&scenes, sample_count) {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     let e_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&e_loop).unwrap();
-    let mut state = block_on(swapchain::State::new(&window));
+    let mut state = match block_on(swapchain::State::new(&window, &scenes, sample_count)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
 
     e_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
@@ -49,8 +133,8 @@ fn main() {
                 Err(e) => match e.downcast_ref::<SwapChainError>() {
                     Some(wgpu::SwapChainError::Lost) => state.resize(state.size),
                     Some(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    Some(e) => eprintln!("{:?}", e),
-                    None => {}
+                    // timeouts, failed captures and the like only cost this frame
+                    _ => eprintln!("{:?}", e),
                 },
             }
         }
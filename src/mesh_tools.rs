@@ -0,0 +1,566 @@
+//! CPU-side processing of indexed triangle lists.
+
+use crate::buffers::{HasPosition, ModelVertex};
+use cgmath::{InnerSpace, Vector3};
+use std::collections::HashMap;
+
+fn position<V: HasPosition>(vertices: &[V], index: u32) -> Vector3<f32> {
+    vertices[index as usize].position().into()
+}
+
+fn normalized(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// Sets every normal to the average of the faces around it, weighted by their
+/// angle at the vertex so it doesn't depend on how polygons were triangulated.
+pub(crate) fn smooth_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let corners = [0, 1, 2].map(|i| position(vertices, triangle[i]));
+        let normal = normalized((corners[1] - corners[0]).cross(corners[2] - corners[0]));
+        for i in 0..3 {
+            let next = corners[(i + 1) % 3] - corners[i];
+            let previous = corners[(i + 2) % 3] - corners[i];
+            normals[triangle[i] as usize] += normal * next.angle(previous).0;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = normalized(normal).into();
+    }
+}
+
+/// Gives every triangle its own vertices, all with the face normal.
+pub(crate) fn flat_normals(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut flat = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| position(vertices, triangle[i]));
+        let normal = normalized((b - a).cross(c - a)).into();
+        flat.extend(triangle.iter().map(|&index| ModelVertex {
+            normal,
+            ..vertices[index as usize]
+        }));
+    }
+    let indices = (0..flat.len() as u32).collect();
+    (flat, indices)
+}
+
+// an unindexed triangle list, the way MikkTSpace reads a mesh
+struct Corners<'a>(&'a mut [ModelVertex]);
+
+impl mikktspace::Geometry for Corners<'_> {
+    fn num_faces(&self) -> usize {
+        self.0.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.0[3 * face + vert].position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.0[3 * face + vert].normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.0[3 * face + vert].tex_coords
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.0[3 * face + vert].tangent = tangent;
+    }
+}
+
+/// Sets MikkTSpace tangents: xyz along increasing u, orthogonal to the normal, and
+/// w the sign that turns `normal x tangent` into the direction of increasing v.
+/// Vertices whose corners end up with different tangents are split.
+pub(crate) fn tangents(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut corners = indices
+        .iter()
+        .map(|&index| vertices[index as usize])
+        .collect::<Vec<_>>();
+    // fails only without triangles, when there is nothing to set
+    mikktspace::generate_tangents(&mut Corners(&mut corners));
+    let corner_indices = (0..corners.len() as u32).collect::<Vec<_>>();
+    dedup(&corners, &corner_indices)
+}
+
+/// Merges vertices that are identical byte for byte.
+pub(crate) fn dedup<V: bytemuck::Pod>(vertices: &[V], indices: &[u32]) -> (Vec<V>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut seen = HashMap::new();
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            *seen.entry(bytemuck::bytes_of(vertex)).or_insert_with(|| {
+                unique.push(*vertex);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect::<Vec<_>>();
+    let indices = indices.iter().map(|&index| remap[index as usize]).collect();
+    (unique, indices)
+}
+
+// size of the simulated post-transform cache
+const CACHE_SIZE: usize = 32;
+
+// Forsyth's weights: recently used vertices and vertices with few triangles left
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // the last triangle's vertices, equally good whichever order it used
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    cache_score + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+/// Reorders triangles so vertices are reused while still in the GPU's
+/// post-transform cache (Tom Forsyth's linear-speed algorithm).
+pub(crate) fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            vertex_triangles[index as usize].push(triangle);
+        }
+    }
+    let mut remaining: Vec<usize> = vertex_triangles.iter().map(Vec::len).collect();
+    let mut scores: Vec<f32> = remaining
+        .iter()
+        .map(|&remaining| vertex_score(None, remaining))
+        .collect();
+    let triangle_score = |scores: &[f32], triangle: usize| {
+        indices[3 * triangle..3 * triangle + 3]
+            .iter()
+            .map(|&index| scores[index as usize])
+            .sum::<f32>()
+    };
+    let mut emitted = vec![false; triangle_count];
+    let mut order = Vec::with_capacity(triangle_count);
+    // the cache, most recent first, with room for a triangle pushing out others
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut next_unemitted = 0;
+    let mut best = None;
+    while order.len() < triangle_count {
+        let triangle = match best {
+            Some(triangle) => triangle,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+        emitted[triangle] = true;
+        order.push(triangle);
+        let corners = &indices[3 * triangle..3 * triangle + 3];
+        for &index in corners {
+            remaining[index as usize] -= 1;
+            cache.retain(|&cached| cached != index);
+        }
+        for &index in corners.iter().rev() {
+            cache.insert(0, index);
+        }
+        let evicted = cache.split_off(cache.len().min(CACHE_SIZE));
+        for &index in &evicted {
+            scores[index as usize] = vertex_score(None, remaining[index as usize]);
+        }
+        for (position, &index) in cache.iter().enumerate() {
+            scores[index as usize] = vertex_score(Some(position), remaining[index as usize]);
+        }
+        // only triangles around cached vertices changed score
+        best = cache
+            .iter()
+            .flat_map(|&index| &vertex_triangles[index as usize])
+            .filter(|&&triangle| !emitted[triangle])
+            .map(|&triangle| (triangle, triangle_score(&scores, triangle)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(triangle, _)| triangle);
+    }
+    let reordered: Vec<u32> = order
+        .iter()
+        .flat_map(|&triangle| indices[3 * triangle..3 * triangle + 3].to_vec())
+        .collect();
+    indices[..reordered.len()].copy_from_slice(&reordered);
+}
+
+/// Reorders cache-optimized triangles in clusters, drawing the clusters that face
+/// away from the middle of the mesh (the centre of its bounding sphere) first so
+/// they hide the ones behind them.
+/// Clusters start wherever the cache would be cold anyway, so run this after
+/// `optimize_vertex_cache`.
+pub(crate) fn optimize_overdraw<V: HasPosition>(indices: &mut [u32], vertices: &[V]) {
+    let triangles = indices.chunks_exact(3).collect::<Vec<_>>();
+    if triangles.is_empty() {
+        return;
+    }
+    let mut starts = vec![0];
+    let mut cache: Vec<u32> = Vec::new();
+    for (number, triangle) in triangles.iter().enumerate() {
+        if number > 0 && triangle.iter().all(|index| !cache.contains(index)) {
+            starts.push(number);
+        }
+        for &index in triangle.iter() {
+            if !cache.contains(&index) {
+                cache.insert(0, index);
+            }
+        }
+        cache.truncate(CACHE_SIZE);
+    }
+    let ends = starts.iter().skip(1).copied().chain([triangles.len()]);
+    let clusters = starts
+        .iter()
+        .copied()
+        .zip(ends)
+        .map(|(start, end)| start..end);
+    let centroid = |triangle: &[u32]| {
+        triangle
+            .iter()
+            .map(|&index| position(vertices, index))
+            .sum::<Vector3<f32>>()
+            / 3.0
+    };
+    // unlike the mean of the triangles, not drawn towards finely tessellated parts
+    let mesh_centre = match bounding_sphere(vertices) {
+        Some((centre, _)) => Vector3::from(centre),
+        None => return,
+    };
+    let mut keyed = clusters
+        .map(|cluster| {
+            let mut area_normal = Vector3::new(0.0, 0.0, 0.0);
+            let mut centre = Vector3::new(0.0, 0.0, 0.0);
+            let mut area = 0.0;
+            for triangle in &triangles[cluster.clone()] {
+                let [a, b, c] = [0, 1, 2].map(|i| position(vertices, triangle[i]));
+                let normal = (b - a).cross(c - a);
+                area_normal += normal;
+                centre += centroid(triangle) * normal.magnitude();
+                area += normal.magnitude();
+            }
+            let centre = if area > 0.0 {
+                centre / area
+            } else {
+                mesh_centre
+            };
+            let key = (centre - mesh_centre).dot(normalized(area_normal));
+            (key, cluster)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let reordered: Vec<u32> = keyed
+        .iter()
+        .flat_map(|(_, cluster)| triangles[cluster.clone()].concat())
+        .collect();
+    indices[..reordered.len()].copy_from_slice(&reordered);
+}
+
+/// An axis aligned box around every vertex, `None` without vertices.
+pub(crate) fn bounding_box<V: HasPosition>(vertices: &[V]) -> Option<([f32; 3], [f32; 3])> {
+    let first = vertices.first()?.position();
+    Some(vertices.iter().fold((first, first), |(min, max), vertex| {
+        let position = vertex.position();
+        (
+            [0, 1, 2].map(|axis| min[axis].min(position[axis])),
+            [0, 1, 2].map(|axis| max[axis].max(position[axis])),
+        )
+    }))
+}
+
+/// A sphere around every vertex as centre and radius, a little larger than the
+/// smallest one (Ritter's algorithm). `None` without vertices.
+pub(crate) fn bounding_sphere<V: HasPosition>(vertices: &[V]) -> Option<([f32; 3], f32)> {
+    let positions = vertices
+        .iter()
+        .map(|vertex| Vector3::from(vertex.position()))
+        .collect::<Vec<_>>();
+    let farthest = |from: Vector3<f32>| {
+        positions
+            .iter()
+            .copied()
+            .max_by(|a, b| (a - from).magnitude2().total_cmp(&(b - from).magnitude2()))
+    };
+    let a = farthest(*positions.first()?)?;
+    let b = farthest(a)?;
+    let mut centre = (a + b) / 2.0;
+    let mut radius = (b - a).magnitude() / 2.0;
+    for &position in &positions {
+        let distance = (position - centre).magnitude();
+        if distance > radius {
+            // grow just enough to touch the outlier from the opposite side
+            radius = (radius + distance) / 2.0;
+            centre = position + (centre - position) * (radius / distance);
+        }
+    }
+    Some((centre.into(), radius))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffers::generate::{grid, icosphere, Colors};
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            tex_coords,
+            color: [1.0; 3],
+            tangent: [0.0; 4],
+        }
+    }
+
+    fn assert_near(actual: [f32; 4], expected: [f32; 4]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 1e-5),
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    // the triangles as a sorted list, each rotated to start at its lowest index so
+    // reordering corners without changing the winding compares equal
+    fn triangle_set(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles = indices
+            .chunks_exact(3)
+            .map(|t| {
+                let first = (0..3).min_by_key(|&i| t[i]).unwrap();
+                [0, 1, 2].map(|i| t[(first + i) % 3])
+            })
+            .collect::<Vec<_>>();
+        triangles.sort();
+        triangles
+    }
+
+    // misses of a FIFO post-transform cache drawing the triangles in order
+    fn cache_misses(indices: &[u32]) -> usize {
+        let mut cache = std::collections::VecDeque::new();
+        let mut misses = 0;
+        for &index in indices {
+            if !cache.contains(&index) {
+                misses += 1;
+                cache.push_back(index);
+                if cache.len() > 16 {
+                    cache.pop_front();
+                }
+            }
+        }
+        misses
+    }
+
+    #[test]
+    fn smooth_normals_average_the_faces_around_a_vertex() {
+        // a cube sharing its 8 corners, every face split along a diagonal
+        let mut vertices = (0..8)
+            .map(|i| [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|bit| bit as f32 * 2.0 - 1.0))
+            .map(|position| vertex(position, [0.0; 2]))
+            .collect::<Vec<_>>();
+        let faces = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let indices = faces
+            .iter()
+            .flat_map(|&[a, b, c, d]| [a, b, c, a, c, d])
+            .collect::<Vec<u32>>();
+        smooth_normals(&mut vertices, &indices);
+        for v in &vertices {
+            // corners touch one or both triangles of a face, but always 90 degrees
+            let expected = Vector3::from(v.position).normalize();
+            let normal = Vector3::from(v.normal);
+            assert!((normal - expected).magnitude() < 1e-5, "{:?}", v);
+        }
+        // a sphere's normals point away from its centre
+        let (sphere, indices) = icosphere(1.0, 2, Colors::RGB);
+        let mut vertices = sphere
+            .iter()
+            .map(|v| vertex(v.position(), [0.0; 2]))
+            .collect::<Vec<_>>();
+        smooth_normals(&mut vertices, &indices);
+        for v in &vertices {
+            let normal = Vector3::from(v.normal);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(Vector3::from(v.position).normalize()) > 0.99);
+        }
+    }
+
+    #[test]
+    fn flat_normals_give_every_corner_the_face_normal() {
+        // a quad folded along its diagonal, the second half standing up in +z
+        let vertices = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+        ]
+        .map(|position| vertex(position, [0.0; 2]));
+        let (flat, indices) = flat_normals(&vertices, &[0, 1, 2, 0, 2, 3]);
+        assert_eq!(flat.len(), 6);
+        assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+        assert!(flat[..3].iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+        let folded = Vector3::new(1.0, -1.0, 1.0).normalize();
+        for v in &flat[3..] {
+            assert!((Vector3::from(v.normal) - folded).magnitude() < 1e-6);
+        }
+        let positions = indices
+            .iter()
+            .map(|&i| flat[i as usize].position)
+            .collect::<Vec<_>>();
+        let expected = [0, 1, 2, 0, 2, 3].map(|i: usize| vertices[i].position);
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn tangents_point_along_increasing_u() {
+        // v runs down the texture, against +y
+        let vertices = [
+            vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+            vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 0.0]),
+        ];
+        let (with_tangents, indices) = tangents(&vertices, &[0, 1, 2, 0, 2, 3]);
+        // one tangent across the quad, so its corners are shared again
+        assert_eq!(with_tangents.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+        for v in &with_tangents {
+            // normal x tangent is +y, the bitangent -y
+            assert_near(v.tangent, [1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn tangents_split_vertices_at_mirrored_texture_coordinates() {
+        // two quads side by side, the texture mirrored at x = 1
+        let u = |x: f32| if x > 1.0 { 2.0 - x } else { x };
+        let vertices = [0.0, 1.0, 2.0]
+            .iter()
+            .flat_map(|&x| [[x, 0.0, 0.0], [x, 1.0, 0.0]])
+            .map(|p| vertex(p, [u(p[0]), 1.0 - p[1]]))
+            .collect::<Vec<_>>();
+        let quads = [0, 2, 3, 0, 3, 1, 2, 4, 5, 2, 5, 3];
+        let (with_tangents, indices) = tangents(&vertices, &quads);
+        // the two vertices on the mirror line are needed once for each side
+        assert_eq!(with_tangents.len(), 8);
+        for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+            let expected = if triangle < 2 {
+                [1.0, 0.0, 0.0, -1.0]
+            } else {
+                [-1.0, 0.0, 0.0, 1.0]
+            };
+            for &index in corners {
+                assert_near(with_tangents[index as usize].tangent, expected);
+            }
+        }
+        let positions = |vertices: &[ModelVertex], indices: &[u32]| {
+            indices
+                .iter()
+                .map(|&i| vertices[i as usize].position)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            positions(&with_tangents, &indices),
+            positions(&vertices, &quads)
+        );
+    }
+
+    #[test]
+    fn dedup_merges_identical_vertices() {
+        let vertices: [[u32; 2]; 4] = [[1, 2], [3, 4], [1, 2], [5, 6]];
+        let (unique, indices) = dedup(&vertices, &[0, 1, 2, 2, 3, 0]);
+        assert_eq!(unique, vec![[1, 2], [3, 4], [5, 6]]);
+        assert_eq!(indices, vec![0, 1, 0, 0, 2, 0]);
+    }
+
+    #[test]
+    fn optimize_vertex_cache_reuses_vertices_and_keeps_the_triangles() {
+        let (vertices, indices) = grid(16, 16, 2.0, 2.0, Colors::RGB);
+        // a fixed shuffle of the triangles, bad for any cache
+        let count = indices.len() / 3;
+        let mut shuffled = (0..count)
+            .map(|i| (i * 97) % count)
+            .flat_map(|t| indices[3 * t..3 * t + 3].to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(triangle_set(&shuffled), triangle_set(&indices));
+        let before = cache_misses(&shuffled);
+        optimize_vertex_cache(&mut shuffled, vertices.len());
+        assert_eq!(triangle_set(&shuffled), triangle_set(&indices));
+        // every vertex has to be transformed once, a good order comes close
+        let after = cache_misses(&shuffled);
+        assert!(after < before / 2, "{} misses, {} before", after, before);
+        assert!(after < vertices.len() * 3 / 2, "{} misses", after);
+    }
+
+    #[test]
+    fn optimize_overdraw_keeps_the_triangles() {
+        for (vertices, mut indices) in [
+            icosphere(1.0, 3, Colors::RGB),
+            grid(8, 8, 2.0, 2.0, Colors::RGB),
+        ] {
+            let original = triangle_set(&indices);
+            optimize_vertex_cache(&mut indices, vertices.len());
+            optimize_overdraw(&mut indices, &vertices);
+            assert_eq!(triangle_set(&indices), original);
+        }
+        let mut empty = Vec::new();
+        optimize_overdraw::<ModelVertex>(&mut empty, &[]);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn bounding_box_spans_the_extremes_of_every_axis() {
+        let vertices = [[1.0, -2.0, 3.0], [-4.0, 5.0, 0.5], [0.0, 0.0, -6.0]]
+            .map(|position| vertex(position, [0.0; 2]));
+        assert_eq!(
+            bounding_box(&vertices),
+            Some(([-4.0, -2.0, -6.0], [1.0, 5.0, 3.0]))
+        );
+        assert_eq!(bounding_box::<ModelVertex>(&[]), None);
+    }
+
+    #[test]
+    fn bounding_sphere_contains_every_vertex() {
+        let two = [[-1.0, 0.0, 0.0], [3.0, 0.0, 0.0]].map(|position| vertex(position, [0.0; 2]));
+        assert_eq!(bounding_sphere(&two), Some(([1.0, 0.0, 0.0], 2.0)));
+        assert_eq!(bounding_sphere::<ModelVertex>(&[]), None);
+        let (vertices, _) = icosphere(2.0, 2, Colors::RGB);
+        // off-centre, with one outlier the first pass misses
+        let mut points = vertices
+            .iter()
+            .map(|v| Vector3::from(v.position()) + Vector3::new(5.0, 0.0, 0.0))
+            .collect::<Vec<_>>();
+        points.push(Vector3::new(5.0, 1.0, 3.0));
+        let vertices = points
+            .iter()
+            .map(|&p| vertex(p.into(), [0.0; 2]))
+            .collect::<Vec<_>>();
+        let (centre, radius) = bounding_sphere(&vertices).unwrap();
+        for p in &points {
+            assert!((p - Vector3::from(centre)).magnitude() <= radius * (1.0 + 1e-5));
+        }
+        // Ritter's sphere stays within a few percent of the smallest one
+        assert!(radius < 3.0 * 1.1, "radius {}", radius);
+    }
+}
@@ -0,0 +1,25 @@
+#version 450
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec2 v_tex_coords;
+layout(location = 2) in vec3 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+// set 0 is the camera of model.vert
+#ifdef TEXTURED
+layout(set = 1, binding = 0) uniform texture2D t_diffuse;
+layout(set = 1, binding = 1) uniform sampler s_diffuse;
+#endif
+
+const vec3 LIGHT_DIR = vec3(0.25, 0.42, 0.87);
+const float AMBIENT = 0.2;
+
+void main() {
+    vec3 color = v_color;
+#ifdef TEXTURED
+    color *= texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords).rgb;
+#endif
+    float diffuse = max(dot(normalize(v_normal), LIGHT_DIR), 0.0);
+    f_color = vec4(color * (AMBIENT + (1.0 - AMBIENT) * diffuse), 1.0);
+}
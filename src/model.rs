@@ -0,0 +1,91 @@
+use crate::{
+    buffers::ModelVertex, gltf_model, mesh_tools, obj, pipeline_creator::BufferRelatedData,
+    texture::TextureData,
+};
+use anyhow::{bail, Result};
+use std::path::Path;
+use wgpu::Device;
+
+/// The parts of a material the model shaders use.
+pub(crate) struct ModelMaterial {
+    pub(crate) name: String,
+    pub(crate) diffuse: [f32; 3],
+    pub(crate) diffuse_texture: Option<TextureData>,
+}
+
+/// Triangles drawn with a single material.
+pub(crate) struct ModelMesh {
+    pub(crate) name: String,
+    pub(crate) vertices: Vec<ModelVertex>,
+    pub(crate) indices: Vec<u32>,
+    pub(crate) material: Option<usize>,
+}
+
+/// Meshes in a common space with the materials they use, loaded from an asset
+/// file. Texture coordinates start at the top left.
+pub(crate) struct Model {
+    pub(crate) meshes: Vec<ModelMesh>,
+    pub(crate) materials: Vec<ModelMaterial>,
+}
+
+impl Model {
+    /// Loads a Wavefront OBJ, glTF or GLB file, picked by extension. glTF node
+    /// transforms are applied to the meshes.
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("obj") => obj::load(path),
+            Some("gltf") | Some("glb") => Ok(gltf_model::GltfScene::load(path)?.bake()),
+            _ => bail!("{:?} is not an OBJ, glTF or GLB file", path),
+        }
+    }
+
+    /// Moves and scales every mesh so the whole model is centred on the origin and
+    /// spans `size` along its longest side.
+    pub(crate) fn fit(&mut self, size: f32) {
+        let boxes = self
+            .meshes
+            .iter()
+            .filter_map(|mesh| mesh_tools::bounding_box(&mesh.vertices));
+        let (min, max) = match boxes.reduce(|(min, max), (mesh_min, mesh_max)| {
+            (
+                [0, 1, 2].map(|axis| min[axis].min(mesh_min[axis])),
+                [0, 1, 2].map(|axis| max[axis].max(mesh_max[axis])),
+            )
+        }) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f32::max);
+        if extent <= 0.0 {
+            return;
+        }
+        let scale = size / extent;
+        for vertex in self.meshes.iter_mut().flat_map(|mesh| &mut mesh.vertices) {
+            for axis in 0..3 {
+                let centre = (min[axis] + max[axis]) / 2.0;
+                vertex.position[axis] = (vertex.position[axis] - centre) * scale;
+            }
+        }
+    }
+
+    /// Reorders the triangles of every mesh for the vertex cache, then for overdraw.
+    pub(crate) fn optimize(&mut self) {
+        for mesh in &mut self.meshes {
+            mesh_tools::optimize_vertex_cache(&mut mesh.indices, mesh.vertices.len());
+            mesh_tools::optimize_overdraw(&mut mesh.indices, &mesh.vertices);
+        }
+    }
+
+    /// All meshes in one vertex and index buffer, for drawing the model in one call.
+    pub(crate) fn create_buffers(&self, device: &Device) -> Result<BufferRelatedData> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for mesh in &self.meshes {
+            let first = vertices.len() as u32;
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend(mesh.indices.iter().map(|index| first + index));
+        }
+        BufferRelatedData::new(device, &vertices, &indices)
+    }
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_normal;
+layout(location = 2) in vec2 a_tex_coords;
+layout(location = 3) in vec3 a_color;
+layout(location = 4) in vec4 a_tangent;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec2 v_tex_coords;
+layout(location = 2) out vec3 v_color;
+
+#include "camera.glsl"
+
+void main() {
+    v_normal = a_normal;
+    v_tex_coords = a_tex_coords;
+    v_color = a_color;
+    gl_Position = view_proj * vec4(a_position, 1.0);
+}
@@ -0,0 +1,462 @@
+//! Wavefront OBJ files with the materials of their `mtllib`s. Every `o`/`g` group
+//! and `usemtl` starts a new mesh and polygons are triangulated as fans. Faces
+//! without normals are flat, or smooth inside an `s` smoothing group.
+
+use crate::{
+    buffers::ModelVertex,
+    mesh_tools,
+    model::{Model, ModelMaterial, ModelMesh},
+    texture::{ColorSpace, TextureData},
+};
+use anyhow::{anyhow, ensure, Context, Result};
+use cgmath::{InnerSpace, Vector3};
+use std::{collections::HashMap, fs::read_to_string, path::Path, str::FromStr};
+
+// a face corner: position, texture coordinate and normal indices, 0 based
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct MeshBuilder {
+    mesh: ModelMesh,
+    // corners already in `mesh.vertices`; flat faces without normals get their own
+    corners: HashMap<Corner, u32>,
+    // corners without normals shared within a smoothing group
+    smooth_corners: HashMap<(usize, Option<usize>, u32), u32>,
+}
+
+impl MeshBuilder {
+    fn new(name: &str, material: Option<usize>) -> Self {
+        Self {
+            mesh: ModelMesh {
+                name: name.to_string(),
+                vertices: Vec::new(),
+                indices: Vec::new(),
+                material,
+            },
+            corners: HashMap::new(),
+            smooth_corners: HashMap::new(),
+        }
+    }
+
+    // gives the smooth corners the average normal of their faces
+    fn finish(self) -> ModelMesh {
+        let mut mesh = self.mesh;
+        if !self.smooth_corners.is_empty() {
+            let mut smooth = mesh.vertices.clone();
+            mesh_tools::smooth_normals(&mut smooth, &mesh.indices);
+            for &index in self.smooth_corners.values() {
+                mesh.vertices[index as usize].normal = smooth[index as usize].normal;
+            }
+        }
+        mesh
+    }
+}
+
+fn parse_word<T: FromStr>(word: Option<&str>, what: &str) -> Result<T> {
+    let word = word.with_context(|| format!("missing {}", what))?;
+    word.parse()
+        .ok()
+        .with_context(|| format!("invalid {} {:?}", what, word))
+}
+
+fn parse_vec3<'a>(words: &mut impl Iterator<Item = &'a str>, what: &str) -> Result<[f32; 3]> {
+    Ok([
+        parse_word(words.next(), what)?,
+        parse_word(words.next(), what)?,
+        parse_word(words.next(), what)?,
+    ])
+}
+
+// OBJ indices count from 1, negative ones back from the last element so far
+fn resolve(index: &str, count: usize, what: &str) -> Result<usize> {
+    let index: i64 = parse_word(Some(index), what)?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    ensure!(
+        (0..count as i64).contains(&resolved),
+        "{} index {} out of range",
+        what,
+        index
+    );
+    Ok(resolved as usize)
+}
+
+fn load_materials(path: &Path, materials: &mut Vec<ModelMaterial>) -> Result<()> {
+    let src = read_to_string(path).with_context(|| format!("cannot read {:?}", path))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for (number, line) in src.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let result = match (words.next(), materials.last_mut()) {
+            (Some("newmtl"), _) => {
+                let name = words.next().context("missing material name")?;
+                materials.push(ModelMaterial {
+                    name: name.to_string(),
+                    diffuse: [1.0; 3],
+                    diffuse_texture: None,
+                });
+                Ok(())
+            }
+            (Some("Kd"), Some(material)) => {
+                parse_vec3(&mut words, "color").map(|color| material.diffuse = color)
+            }
+            // the file name comes after any options
+            (Some("map_Kd"), Some(material)) => match line.split_whitespace().last() {
+                Some(file) if file != "map_Kd" => {
+                    TextureData::load(dir.join(file), ColorSpace::Srgb)
+                        .map(|texture| material.diffuse_texture = Some(texture))
+                }
+                _ => Err(anyhow!("missing texture file")),
+            },
+            _ => Ok(()),
+        };
+        result.with_context(|| format!("{:?} line {}", path, number + 1))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn load(path: &Path) -> Result<Model> {
+    let src = read_to_string(path).with_context(|| format!("cannot read {:?}", path))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&src, |file| {
+        let mut materials = Vec::new();
+        load_materials(&dir.join(file), &mut materials)?;
+        Ok(materials)
+    })
+    .with_context(|| format!("invalid OBJ file {:?}", path))
+}
+
+// `mtllib` loads the materials of the named file
+fn parse(src: &str, mtllib: impl Fn(&str) -> Result<Vec<ModelMaterial>>) -> Result<Model> {
+    let mut positions = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    let mut materials: Vec<ModelMaterial> = Vec::new();
+    let mut meshes = Vec::new();
+    let mut current = MeshBuilder::new("", None);
+    // 0 while smoothing is off
+    let mut smoothing_group = 0;
+    for (number, line) in src.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let result = match words.next() {
+            Some("v") => parse_vec3(&mut words, "position").map(|v| positions.push(v)),
+            Some("vn") => parse_vec3(&mut words, "normal").map(|vn| normals.push(vn)),
+            Some("vt") => parse_word(words.next(), "texture coordinate").and_then(|u: f32| {
+                let v: f32 = words
+                    .next()
+                    .map_or(Ok(0.0), |v| parse_word(Some(v), "texture coordinate"))?;
+                tex_coords.push([u, 1.0 - v]);
+                Ok(())
+            }),
+            Some(keyword @ "o") | Some(keyword @ "g") | Some(keyword @ "usemtl") => {
+                let name = words.collect::<Vec<_>>().join(" ");
+                let next = match keyword {
+                    "usemtl" => materials
+                        .iter()
+                        .position(|material| material.name == name)
+                        .with_context(|| format!("unknown material {}", name))
+                        .map(|material| MeshBuilder::new(&current.mesh.name, Some(material))),
+                    _ => Ok(MeshBuilder::new(&name, current.mesh.material)),
+                };
+                next.map(|next| {
+                    let done = std::mem::replace(&mut current, next);
+                    if !done.mesh.indices.is_empty() {
+                        meshes.push(done.finish());
+                    }
+                })
+            }
+            Some("s") => match words.next() {
+                Some("off") => {
+                    smoothing_group = 0;
+                    Ok(())
+                }
+                group => parse_word(group, "smoothing group").map(|group| smoothing_group = group),
+            },
+            Some("mtllib") => words.try_for_each(|file| {
+                materials.extend(mtllib(file)?);
+                Ok(())
+            }),
+            Some("f") => {
+                let material = current.mesh.material.map(|material| &materials[material]);
+                add_face(
+                    &mut current,
+                    words,
+                    &positions,
+                    &tex_coords,
+                    &normals,
+                    material,
+                    smoothing_group,
+                )
+            }
+            _ => Ok(()),
+        };
+        result.with_context(|| format!("line {}", number + 1))?;
+    }
+    if !current.mesh.indices.is_empty() {
+        meshes.push(current.finish());
+    }
+    ensure!(!meshes.is_empty(), "no faces");
+    for mesh in &mut meshes {
+        let (vertices, indices) = mesh_tools::tangents(&mesh.vertices, &mesh.indices);
+        mesh.vertices = vertices;
+        mesh.indices = indices;
+    }
+    Ok(Model { meshes, materials })
+}
+
+fn add_face<'a>(
+    builder: &mut MeshBuilder,
+    words: impl Iterator<Item = &'a str>,
+    positions: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+    normals: &[[f32; 3]],
+    material: Option<&ModelMaterial>,
+    smoothing_group: u32,
+) -> Result<()> {
+    let corners = words
+        .map(|word| {
+            let mut parts = word.split('/');
+            let position = resolve(parts.next().unwrap_or(""), positions.len(), "position")?;
+            let tex_coord = match parts.next() {
+                Some("") | None => None,
+                Some(index) => Some(resolve(index, tex_coords.len(), "texture coordinate")?),
+            };
+            let normal = match parts.next() {
+                Some("") | None => None,
+                Some(index) => Some(resolve(index, normals.len(), "normal")?),
+            };
+            Ok((position, tex_coord, normal))
+        })
+        .collect::<Result<Vec<Corner>>>()?;
+    ensure!(corners.len() >= 3, "face with fewer than 3 vertices");
+    // faces without normals are lit as flat outside smoothing groups
+    let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(positions[corners[i].0]));
+    let face_normal = (b - a).cross(c - a);
+    let face_normal = if face_normal.magnitude2() > 0.0 {
+        face_normal.normalize().into()
+    } else {
+        [0.0; 3]
+    };
+    let color = material.map_or([1.0; 3], |material| material.diffuse);
+    let mesh = &mut builder.mesh;
+    let mut indices = Vec::with_capacity(corners.len());
+    for corner in &corners {
+        let shared = match corner.2 {
+            Some(_) => builder.corners.get(corner),
+            None if smoothing_group != 0 => {
+                builder
+                    .smooth_corners
+                    .get(&(corner.0, corner.1, smoothing_group))
+            }
+            None => None,
+        };
+        let index = match shared {
+            Some(index) => *index,
+            None => {
+                let index = mesh.vertices.len() as u32;
+                mesh.vertices.push(ModelVertex {
+                    position: positions[corner.0],
+                    normal: corner.2.map_or(face_normal, |normal| normals[normal]),
+                    tex_coords: corner.1.map_or([0.0; 2], |tex_coord| tex_coords[tex_coord]),
+                    color,
+                    tangent: [0.0; 4],
+                });
+                match corner.2 {
+                    Some(_) => builder.corners.insert(*corner, index),
+                    None if smoothing_group != 0 => builder
+                        .smooth_corners
+                        .insert((corner.0, corner.1, smoothing_group), index),
+                    None => None,
+                };
+                index
+            }
+        };
+        indices.push(index);
+    }
+    for i in 1..indices.len() - 1 {
+        mesh.indices
+            .extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material(name: &str, diffuse: [f32; 3]) -> ModelMaterial {
+        ModelMaterial {
+            name: name.to_string(),
+            diffuse,
+            diffuse_texture: None,
+        }
+    }
+
+    fn no_mtllib(file: &str) -> Result<Vec<ModelMaterial>> {
+        Err(anyhow!("unexpected mtllib {}", file))
+    }
+
+    fn positions(mesh: &ModelMesh) -> Vec<[f32; 3]> {
+        mesh.indices
+            .iter()
+            .map(|&index| mesh.vertices[index as usize].position)
+            .collect()
+    }
+
+    const QUAD: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+";
+
+    #[test]
+    fn triangulates_polygons_as_fans() {
+        let model = parse(&format!("{}f 1 2 3 4\n", QUAD), no_mtllib).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(
+            positions(mesh),
+            vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_negative_indices_relative_to_the_elements_so_far() {
+        let absolute = parse(&format!("{}f 2 3 4\n", QUAD), no_mtllib).unwrap();
+        let relative = parse(&format!("{}f -3 -2 -1\n", QUAD), no_mtllib).unwrap();
+        assert_eq!(
+            positions(&absolute.meshes[0]),
+            positions(&relative.meshes[0])
+        );
+        // -1 is the last position before the face, not the last in the file
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\n";
+        let model = parse(src, no_mtllib).unwrap();
+        assert_eq!(model.meshes[0].vertices[2].position, [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_texture_coordinates_and_normals() {
+        let src = format!(
+            "{}vt 0.25 0.75\nvt 0.5\nvn 0 0 -1\nf 1/1/1 2//1 3/2/1\n",
+            QUAD
+        );
+        let model = parse(&src, no_mtllib).unwrap();
+        let vertices = &model.meshes[0].vertices;
+        // v points up in OBJ and down in wgpu
+        assert_eq!(vertices[0].tex_coords, [0.25, 0.25]);
+        assert_eq!(vertices[1].tex_coords, [0.0, 0.0]);
+        assert_eq!(vertices[2].tex_coords, [0.5, 1.0]);
+        assert!(vertices
+            .iter()
+            .all(|vertex| vertex.normal == [0.0, 0.0, -1.0]));
+        assert!(vertices.iter().all(|vertex| vertex.color == [1.0; 3]));
+    }
+
+    #[test]
+    fn shares_corners_with_normals_only() {
+        let src = format!("{}vn 0 0 1\nf 1//1 2//1 3//1\nf 1//1 3//1 4//1\n", QUAD);
+        let model = parse(&src, no_mtllib).unwrap();
+        assert_eq!(model.meshes[0].vertices.len(), 4);
+        assert_eq!(model.meshes[0].indices, vec![0, 1, 2, 0, 2, 3]);
+        // without normals every face is flat, so faces facing apart share nothing
+        let src = format!("{}f 1 2 3\nf 1 3 2\n", QUAD);
+        let model = parse(&src, no_mtllib).unwrap();
+        assert_eq!(model.meshes[0].vertices.len(), 6);
+    }
+
+    #[test]
+    fn computes_face_normals_when_missing() {
+        let src = format!("{}f 1 2 3\nf 1 3 2\n", QUAD);
+        let model = parse(&src, no_mtllib).unwrap();
+        let normals = model.meshes[0]
+            .vertices
+            .iter()
+            .map(|vertex| vertex.normal)
+            .collect::<Vec<_>>();
+        assert_eq!(normals[..3], [[0.0, 0.0, 1.0]; 3]);
+        assert_eq!(normals[3..], [[0.0, 0.0, -1.0]; 3]);
+    }
+
+    #[test]
+    fn smooths_faces_without_normals_in_smoothing_groups() {
+        // the three faces of a cube around its corner at the origin
+        let corner = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nv 1 1 0\nv 0 1 1\nv 1 0 1\n";
+        let faces = "f 1 3 5 2\nf 1 2 7 4\nf 1 4 6 3\n";
+        let origin_normals = |smoothing: &str| {
+            let src = format!("{}{}{}", corner, smoothing, faces);
+            let model = parse(&src, no_mtllib).unwrap();
+            model.meshes[0]
+                .vertices
+                .iter()
+                .filter(|vertex| vertex.position == [0.0; 3])
+                .map(|vertex| Vector3::from(vertex.normal))
+                .collect::<Vec<_>>()
+        };
+        let averaged = Vector3::new(-1.0, -1.0, -1.0).normalize();
+        for normal in origin_normals("s 1\n") {
+            assert!((normal - averaged).magnitude() < 1e-5, "{:?}", normal);
+        }
+        let mut flat = origin_normals("s 1\ns off\n")
+            .iter()
+            .map(|&normal| normal.into())
+            .collect::<Vec<[f32; 3]>>();
+        flat.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            flat,
+            vec![[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]]
+        );
+    }
+
+    #[test]
+    fn splits_meshes_at_groups_and_materials() {
+        let src = format!(
+            "mtllib colors.mtl\n{}o empty\ng first\nusemtl red\nf 1 2 3\nusemtl blue\nf 1 3 4\ng second\nf 2 3 4\n",
+            QUAD
+        );
+        let model = parse(&src, |file| {
+            assert_eq!(file, "colors.mtl");
+            Ok(vec![
+                material("red", [1.0, 0.0, 0.0]),
+                material("blue", [0.0, 0.0, 1.0]),
+            ])
+        })
+        .unwrap();
+        assert_eq!(model.materials.len(), 2);
+        let meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| (mesh.name.as_str(), mesh.material, mesh.vertices[0].color))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            meshes,
+            vec![
+                ("first", Some(0), [1.0, 0.0, 0.0]),
+                ("first", Some(1), [0.0, 0.0, 1.0]),
+                ("second", Some(1), [0.0, 0.0, 1.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let error = |src: &str| format!("{:#}", parse(src, no_mtllib).err().unwrap());
+        assert!(error(&format!("{}f 1 2 5\n", QUAD)).contains("line 6"));
+        assert!(error(&format!("{}f 1 2 -5\n", QUAD)).contains("out of range"));
+        assert!(error(&format!("{}f 1 2\n", QUAD)).contains("fewer than 3"));
+        assert!(error(&format!("{}usemtl missing\n", QUAD)).contains("unknown material"));
+        assert!(error("v 0 0 x\n").contains("invalid position"));
+        assert!(error(QUAD).contains("no faces"));
+        assert!(error("mtllib colors.mtl\n").contains("unexpected mtllib"));
+    }
+}
@@ -1,229 +1,575 @@
-use crate::{buffers, swapchain};
-use image::{self, GenericImageView};
-use std::num::NonZeroU32;
+use crate::{
+    atlas::TextureAtlas,
+    buffers::{self, VertexFormat},
+    camera::{CameraBinding, CAMERA_GROUP},
+    scene::Scenes,
+    shaders::{self, CompiledShader},
+    swapchain,
+    texture::{Mipmaps, SamplerOptions, TextureData},
+};
+use anyhow::{bail, ensure, Context, Result};
+use image::RgbaImage;
 use wgpu::{
-    include_spirv,
     util::{BufferInitDescriptor, DeviceExt},
-    Buffer, BufferUsage, ColorTargetState, ColorWrite, Device, Face, FragmentState, FrontFace,
-    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
-    Queue, RenderPipeline, RenderPipelineDescriptor, SwapChainDescriptor, VertexState,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, BlendState, Buffer, BufferUsage, Color, ColorTargetState, ColorWrite,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, Face, FragmentState, FrontFace,
+    IndexFormat, Limits, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPipeline, RenderPipelineDescriptor, Sampler, ShaderModule,
+    StencilState, SwapChainDescriptor, Texture, TextureFormat, TextureView, TextureViewDescriptor,
+    VertexBufferLayout, VertexState,
 };
 
 pub(crate) struct BufferRelatedData {
     pub(crate) vertex_buffer: Buffer,
     pub(crate) index_buffer: Buffer,
     pub(crate) num_indices: u32,
+    pub(crate) index_format: IndexFormat,
+}
+
+impl BufferRelatedData {
+    /// Uploads a mesh, with 16 bit indices unless there are too many vertices for
+    /// them. Fails when an index is out of range.
+    pub(crate) fn new<V: bytemuck::Pod, I: Copy + Into<u32>>(
+        device: &Device,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<Self> {
+        if let Some(index) = indices
+            .iter()
+            .map(|&index| index.into())
+            .find(|&index| index as usize >= vertices.len())
+        {
+            bail!(
+                "index {} out of range for {} vertices",
+                index,
+                vertices.len()
+            );
+        }
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("vertex buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: BufferUsage::VERTEX,
+        });
+        // 0xFFFF restarts strips of 16 bit indices, so it can't be a vertex
+        let (index_format, contents) = if vertices.len() <= u16::MAX as usize {
+            let indices: Vec<u16> = indices.iter().map(|&index| index.into() as u16).collect();
+            (IndexFormat::Uint16, bytemuck::cast_slice(&indices).to_vec())
+        } else {
+            let indices: Vec<u32> = indices.iter().map(|&index| index.into()).collect();
+            (IndexFormat::Uint32, bytemuck::cast_slice(&indices).to_vec())
+        };
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("index buffer"),
+            contents: &contents,
+            usage: BufferUsage::INDEX,
+        });
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            index_format,
+        })
+    }
+}
+
+/// The instances drawn of a mesh, rewritten as often as they change.
+pub(crate) struct InstanceBuffer {
+    pub(crate) buffer: Buffer,
+    pub(crate) count: u32,
+    // instances the buffer has room for
+    capacity: usize,
+}
+
+impl InstanceBuffer {
+    pub(crate) fn new(device: &Device, instances: &[buffers::Instance]) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, instances),
+            count: instances.len() as u32,
+            capacity: instances.len(),
+        }
+    }
+
+    fn create_buffer(device: &Device, instances: &[buffers::Instance]) -> Buffer {
+        device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("instance buffer"),
+            contents: bytemuck::cast_slice(instances),
+            usage: BufferUsage::VERTEX | BufferUsage::COPY_DST,
+        })
+    }
+
+    /// Replaces the instances, with a new buffer only when they outgrow the old one.
+    pub(crate) fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        instances: &[buffers::Instance],
+    ) {
+        if instances.len() > self.capacity {
+            self.buffer = Self::create_buffer(device, instances);
+            self.capacity = instances.len();
+        } else {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        }
+        self.count = instances.len() as u32;
+    }
+}
+
+/// Describes a render pipeline with defaults for everything but the shaders:
+/// vertex buffers and bind group layouts reflected from the shaders, back-face
+/// culled triangle lists of 16 bit indices, no blending, no depth and a single sample.
+/// A transparent render only needs `blend`, a custom one `primitive`,
+/// `multisample` or `bind_group_layouts`.
+/// Kept by `SpecificRender` so the pipeline can be rebuilt.
+#[derive(Clone)]
+pub(crate) struct RenderPipelineBuilder {
+    label: String,
+    vertex_shader: &'static CompiledShader,
+    fragment_shader: &'static CompiledShader,
+    vertex_buffers: Vec<VertexBufferLayout<'static>>,
+    // reflected from the shaders when None
+    bind_group_layouts: Option<Vec<BindGroupLayoutDescriptor<'static>>>,
+    primitive: PrimitiveState,
+    index_format: IndexFormat,
+    multisample: MultisampleState,
+    blend: Option<BlendState>,
+    depth_stencil: Option<DepthStencilState>,
+}
+
+impl RenderPipelineBuilder {
+    pub(crate) fn new(
+        label: &str,
+        vertex_shader: &'static CompiledShader,
+        fragment_shader: &'static CompiledShader,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            vertex_shader,
+            fragment_shader,
+            vertex_buffers: vertex_shader.vertex_layout.iter().cloned().collect(),
+            bind_group_layouts: None,
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                polygon_mode: PolygonMode::Fill,
+                clamp_depth: false,
+                conservative: false,
+            },
+            index_format: IndexFormat::Uint16,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            blend: None,
+            depth_stencil: None,
+        }
+    }
+
+    pub(crate) fn vertex_buffers(mut self, vertex_buffers: &[VertexBufferLayout<'static>]) -> Self {
+        self.vertex_buffers = vertex_buffers.to_vec();
+        self
+    }
+
+    /// Replaces the bind group layouts reflected from the shaders, e.g. to share
+    /// one layout with dynamic offsets between pipelines.
+    #[allow(dead_code)]
+    pub(crate) fn bind_group_layouts(
+        mut self,
+        bind_group_layouts: &[BindGroupLayoutDescriptor<'static>],
+    ) -> Self {
+        self.bind_group_layouts = Some(bind_group_layouts.to_vec());
+        self
+    }
+
+    pub(crate) fn topology(mut self, topology: PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        // indexed strips need to know which index restarts the strip
+        self.primitive.strip_index_format = match topology {
+            PrimitiveTopology::LineStrip | PrimitiveTopology::TriangleStrip => {
+                Some(self.index_format)
+            }
+            _ => None,
+        };
+        self
+    }
+
+    /// The format of the index buffers drawn with the pipeline, which strips need.
+    pub(crate) fn index_format(mut self, index_format: IndexFormat) -> Self {
+        self.index_format = index_format;
+        if self.primitive.strip_index_format.is_some() {
+            self.primitive.strip_index_format = Some(index_format);
+        }
+        self
+    }
+
+    pub(crate) fn cull_mode(mut self, cull_mode: Option<Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    /// Replaces topology, culling and the rest at once. Strips drawn indexed need
+    /// a `strip_index_format` matching the index buffers.
+    #[allow(dead_code)]
+    pub(crate) fn primitive(mut self, primitive: PrimitiveState) -> Self {
+        self.primitive = primitive;
+        self
+    }
+
+    /// Replaces the sample count, mask and alpha to coverage at once.
+    #[allow(dead_code)]
+    pub(crate) fn multisample(mut self, multisample: MultisampleState) -> Self {
+        self.multisample = multisample;
+        self
+    }
+
+    /// Samples per pixel of the color and depth targets drawn to.
+    pub(crate) fn sample_count(mut self, sample_count: u32) -> Self {
+        self.multisample.count = sample_count;
+        self
+    }
+
+    /// Blends fragments into the color target instead of replacing it.
+    pub(crate) fn blend(mut self, blend: BlendState) -> Self {
+        self.blend = Some(blend);
+        self
+    }
+
+    pub(crate) fn depth_stencil(mut self, depth_stencil: DepthStencilState) -> Self {
+        self.depth_stencil = Some(depth_stencil);
+        self
+    }
+
+    /// Tests fragments against a depth target of `format` and writes the ones that pass.
+    pub(crate) fn depth(self, format: TextureFormat, compare: CompareFunction) -> Self {
+        self.depth_stencil(DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: compare,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        })
+    }
+
+    pub(crate) fn depth_stencil_state(&self) -> Option<&DepthStencilState> {
+        self.depth_stencil.as_ref()
+    }
+
+    #[cfg_attr(not(feature = "hot-reload"), allow(dead_code))]
+    pub(crate) fn vertex_shader(&self) -> &'static CompiledShader {
+        self.vertex_shader
+    }
+
+    #[cfg_attr(not(feature = "hot-reload"), allow(dead_code))]
+    pub(crate) fn fragment_shader(&self) -> &'static CompiledShader {
+        self.fragment_shader
+    }
+
+    pub(crate) fn build(&self, device: &Device, format: TextureFormat) -> RenderPipeline {
+        let vs_module = self.vertex_shader.create_module(device);
+        let fs_module = self.fragment_shader.create_module(device);
+        self.build_with_modules(device, format, &vs_module, &fs_module)
+    }
+
+    pub(crate) fn build_with_modules(
+        &self,
+        device: &Device,
+        format: TextureFormat,
+        vs_module: &ShaderModule,
+        fs_module: &ShaderModule,
+    ) -> RenderPipeline {
+        // create pipeline layout
+        let bind_group_layouts = match &self.bind_group_layouts {
+            Some(descriptors) => descriptors
+                .iter()
+                .map(|descriptor| device.create_bind_group_layout(descriptor))
+                .collect(),
+            None => shaders::create_bind_group_layouts(
+                device,
+                &[self.vertex_shader, self.fragment_shader],
+            ),
+        };
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("render pipeline layout"),
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        // create render pipeline
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(&self.label),
+            layout: Some(&render_pipeline_layout),
+            vertex: VertexState {
+                module: vs_module,
+                entry_point: "main",
+                buffers: &self.vertex_buffers,
+            },
+            fragment: Some(FragmentState {
+                module: fs_module,
+                entry_point: "main",
+                targets: &[ColorTargetState {
+                    format,
+                    blend: self.blend,
+                    write_mask: ColorWrite::ALL,
+                }],
+            }),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: self.multisample,
+        })
+    }
+}
+
+/// A texture with everything needed to sample it in a shader.
+pub(crate) struct DiffuseTexture {
+    pub(crate) texture: Texture,
+    // kept with the bind group that uses them
+    #[allow(dead_code)]
+    pub(crate) view: TextureView,
+    #[allow(dead_code)]
+    pub(crate) sampler: Sampler,
+    pub(crate) bind_group: BindGroup,
 }
 
 pub(crate) struct SpecificRender {
+    pub(crate) name: String,
+    pub(crate) pipeline_builder: RenderPipelineBuilder,
     pub(crate) render_pipeline: RenderPipeline,
     pub(crate) buffer_related: Option<BufferRelatedData>,
+    // follows the mouse when None
+    pub(crate) clear_color: Option<Color>,
+    pub(crate) texture: Option<DiffuseTexture>,
+    // the group of the texture, after the camera when there is one
+    pub(crate) texture_group: u32,
+    pub(crate) camera: Option<CameraBinding>,
+    // a crowd of the mesh, animated in `State::update`
+    pub(crate) instances: Option<InstanceBuffer>,
+}
+
+impl SpecificRender {
+    pub(crate) fn new(
+        device: &Device,
+        format: TextureFormat,
+        name: &str,
+        pipeline_builder: RenderPipelineBuilder,
+        buffer_related: Option<BufferRelatedData>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            render_pipeline: pipeline_builder.build(device, format),
+            pipeline_builder,
+            buffer_related,
+            clear_color: None,
+            texture: None,
+            texture_group: 0,
+            camera: None,
+            instances: None,
+        }
+    }
+
+    pub(crate) fn with_clear_color(mut self, clear_color: Option<Color>) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    pub(crate) fn with_texture(mut self, texture: Option<DiffuseTexture>, group: u32) -> Self {
+        self.texture = texture;
+        self.texture_group = group;
+        self
+    }
+
+    pub(crate) fn with_camera(mut self, camera: Option<CameraBinding>) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub(crate) fn with_instances(mut self, instances: Option<InstanceBuffer>) -> Self {
+        self.instances = instances;
+        self
+    }
 }
 
 impl swapchain::State {
     pub(crate) fn create_specific_render_pipelines(
         device: &Device,
+        queue: &Queue,
         sc_desc: &SwapChainDescriptor,
-    ) -> Vec<SpecificRender> {
-        let primitive = PrimitiveState {
-            topology: PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: FrontFace::Ccw,
-            cull_mode: Some(Face::Back),
-            polygon_mode: PolygonMode::Fill,
-            clamp_depth: false,
-            conservative: false,
-        };
-        let multisample = MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        };
-        let simple_specific_render;
-        {
-            let vs_module = device.create_shader_module(&include_spirv!("shader.vert.spv"));
-            let fs_module = device.create_shader_module(&include_spirv!("shader.frag.spv"));
-            // create pipeline layout
-            let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-                label: Some("render pipeline layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
-            // create render pipeline
-            let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("render pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: VertexState {
-                    module: &vs_module,
-                    entry_point: "main",
-                    buffers: &[],
-                },
-                fragment: Some(FragmentState {
-                    module: &fs_module,
-                    entry_point: "main",
-                    targets: &[ColorTargetState {
-                        format: sc_desc.format,
-                        blend: None,
-                        write_mask: ColorWrite::ALL,
-                    }],
-                }),
-                primitive: primitive,
-                depth_stencil: None,
-                multisample: multisample,
-            });
-            simple_specific_render = SpecificRender {
-                render_pipeline,
-                buffer_related: None,
-            };
-        }
-        let challenge_specific_render;
-        {
-            let vs_module = device.create_shader_module(&include_spirv!("challenge.vert.spv"));
-            let fs_module = device.create_shader_module(&include_spirv!("challenge.frag.spv"));
-            // create pipeline layout
-            let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-                label: Some("render pipeline layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
-            // create render pipeline
-            let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("challenge render pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: VertexState {
-                    module: &vs_module,
-                    entry_point: "main",
-                    buffers: &[buffers::Vertex::desc()],
+        sample_count: u32,
+        scenes: &Scenes,
+    ) -> Result<Vec<SpecificRender>> {
+        scenes
+            .renders
+            .iter()
+            .map(|scene| {
+                let builder = RenderPipelineBuilder::new(
+                    &scene.name,
+                    scene.vertex_shader,
+                    scene.fragment_shader,
+                )
+                .topology(scene.topology)
+                .cull_mode(scene.cull_mode)
+                .sample_count(sample_count);
+                let builder = match scene.depth {
+                    Some((format, compare)) => builder.depth(format, compare),
+                    None => builder,
+                };
+                let (mut builder, mut buffer_related) = match (scene.mesh, &scene.model) {
+                    (Some(mesh), _) if scene.instances.is_some() => (
+                        builder.vertex_buffers(&[mesh.vertex_layout(), buffers::Instance::desc()]),
+                        Some(mesh.create_buffers(device, scene.color)?),
+                    ),
+                    (Some(mesh), _) => (
+                        builder.vertex_buffers(&[mesh.vertex_layout()]),
+                        Some(mesh.create_buffers(device, scene.color)?),
+                    ),
+                    (None, Some(model)) => (
+                        builder.vertex_buffers(&[buffers::ModelVertex::desc()]),
+                        Some(model.create_buffers(device)?),
+                    ),
+                    (None, None) => (builder, None),
+                };
+                let layouts = || {
+                    shaders::create_bind_group_layouts(
+                        device,
+                        &[scene.vertex_shader, scene.fragment_shader],
+                    )
+                };
+                // the fragment shader samples from its only group with bindings
+                let texture_group = scene
+                    .fragment_shader
+                    .bind_group_layouts
+                    .iter()
+                    .position(|layout| !layout.entries.is_empty())
+                    .unwrap_or(0);
+                let mut texture = match &scene.texture {
+                    Some(scene_texture) => Some(Self::create_texture(
+                        device,
+                        queue,
+                        &layouts()[texture_group],
+                        &scene_texture.data,
+                        scene_texture.mipmaps,
+                        scene_texture.sampler,
+                    )?),
+                    None => None,
+                };
+                if !scene.sprites.is_empty() {
+                    let (sprite_buffers, atlas_texture) = Self::create_sprites(
+                        device,
+                        queue,
+                        &layouts()[texture_group],
+                        &scene.sprites,
+                    )?;
+                    // the atlas keeps the transparent parts of the sprites
+                    builder = builder
+                        .vertex_buffers(&[buffers::TexturedVertex::desc()])
+                        .blend(BlendState::ALPHA_BLENDING);
+                    buffer_related = Some(sprite_buffers);
+                    texture = Some(atlas_texture);
+                }
+                if let Some(buffer_related) = &buffer_related {
+                    builder = builder.index_format(buffer_related.index_format);
+                }
+                let camera = scene.camera.map(|(camera, controller)| {
+                    CameraBinding::new(
+                        device,
+                        &layouts()[CAMERA_GROUP as usize],
+                        camera,
+                        controller,
+                        sc_desc.width as f32 / sc_desc.height as f32,
+                    )
+                });
+                Ok(SpecificRender::new(
+                    device,
+                    sc_desc.format,
+                    &scene.name,
+                    builder,
+                    buffer_related,
+                )
+                .with_clear_color(scene.clear_color)
+                .with_texture(texture, texture_group as u32)
+                .with_camera(camera)
+                .with_instances(
+                    scene
+                        .instances
+                        .map(|count| InstanceBuffer::new(device, &buffers::crowd(count, 0.0))),
+                ))
+            })
+            .collect()
+    }
+
+    /// Packs `sprites` into a single atlas page and lays them out in a row of quads.
+    fn create_sprites(
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        sprites: &[RgbaImage],
+    ) -> Result<(BufferRelatedData, DiffuseTexture)> {
+        let max_size = Limits::default().max_texture_dimension_2d;
+        let mut atlas = TextureAtlas::new(256, max_size);
+        let ids = sprites
+            .iter()
+            .map(|sprite| atlas.insert(sprite))
+            .collect::<Result<Vec<_>>>()?;
+        atlas.upload(device, queue, layout)?;
+        let rects = ids
+            .iter()
+            .zip(sprites)
+            .map(|(id, sprite)| {
+                let rect = atlas.rect(*id);
+                ensure!(rect.page == 0, "sprites don't fit one atlas page");
+                Ok((
+                    rect.min,
+                    rect.max,
+                    sprite.height() as f32 / sprite.width() as f32,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (vertices, indices) = buffers::sprite_row(&rects);
+        let texture = atlas
+            .into_textures()
+            .pop()
+            .flatten()
+            .context("atlas page was not uploaded")?;
+        Ok((
+            BufferRelatedData::new(device, &vertices, &indices)?,
+            texture,
+        ))
+    }
+
+    /// Uploads `data` and binds it with a sampler to a bind group of `layout`:
+    /// the texture at binding 0 and the sampler at binding 1.
+    pub(crate) fn create_texture(
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        data: &TextureData,
+        mipmaps: Mipmaps,
+        sampler: SamplerOptions,
+    ) -> Result<DiffuseTexture> {
+        let label = data.label.as_str();
+        let texture = data.upload(device, queue, mipmaps)?;
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = sampler.create_sampler(device, label);
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
                 },
-                fragment: Some(FragmentState {
-                    module: &fs_module,
-                    entry_point: "main",
-                    targets: &[ColorTargetState {
-                        format: sc_desc.format,
-                        blend: None,
-                        write_mask: ColorWrite::ALL,
-                    }],
-                }),
-                primitive: primitive.clone(),
-                depth_stencil: None,
-                multisample: multisample.clone(),
-            });
-            let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("vertex buffer"),
-                contents: bytemuck::cast_slice(buffers::PENTAGON_VERTICES),
-                usage: BufferUsage::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("index buffer"),
-                contents: bytemuck::cast_slice(buffers::PENTAGON_INDICES),
-                usage: BufferUsage::INDEX,
-            });
-            let num_indices = buffers::PENTAGON_INDICES.len() as u32;
-            challenge_specific_render = SpecificRender {
-                render_pipeline,
-                buffer_related: Some(BufferRelatedData {
-                    vertex_buffer,
-                    index_buffer,
-                    num_indices,
-                }),
-            };
-        }
-        let challenge2_specific_render;
-        {
-            let vs_module = device.create_shader_module(&include_spirv!("challenge.vert.spv"));
-            let fs_module = device.create_shader_module(&include_spirv!("challenge.frag.spv"));
-            // create pipeline layout
-            let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-                label: Some("render pipeline layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
-            // create render pipeline
-            let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("challenge 2 hexagon ender pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: VertexState {
-                    module: &vs_module,
-                    entry_point: "main",
-                    buffers: &[buffers::Vertex::desc()],
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&sampler),
                 },
-                fragment: Some(FragmentState {
-                    module: &fs_module,
-                    entry_point: "main",
-                    targets: &[ColorTargetState {
-                        format: sc_desc.format,
-                        blend: None,
-                        write_mask: ColorWrite::ALL,
-                    }],
-                }),
-                primitive,
-                depth_stencil: None,
-                multisample,
-            });
-            let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("vertex buffer"),
-                contents: bytemuck::cast_slice(buffers::HEXAGON_VERTICES),
-                usage: BufferUsage::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("index buffer"),
-                contents: bytemuck::cast_slice(buffers::HEXAGON_INDICES),
-                usage: BufferUsage::INDEX,
-            });
-            let num_indices = buffers::HEXAGON_INDICES.len() as u32;
-            challenge2_specific_render = SpecificRender {
-                render_pipeline,
-                buffer_related: Some(BufferRelatedData {
-                    vertex_buffer,
-                    index_buffer,
-                    num_indices,
-                }),
-            };
-        }
-        vec![
-            simple_specific_render,
-            challenge_specific_render,
-            challenge2_specific_render,
-        ]
-    }
-
-    pub(crate) fn create_texture(device: &Device, queue: &Queue) {
-        let diffuse_bytes = include_bytes!("neutron.jpg");
-        let diffuse_img = image::load_from_memory(diffuse_bytes).unwrap();
-        let diffuse_rgba = diffuse_img.as_rgba8().unwrap();
-        let dimension = diffuse_img.dimensions();
-        let tex_size = wgpu::Extent3d {
-            width: dimension.0,
-            height: dimension.1,
-            depth_or_array_layers: 1,
-        };
-        let diffuse_tex = device.create_texture(&wgpu::TextureDescriptor {
-            size: tex_size,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            mip_level_count: 1,
-            sample_count: 1,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: Some("diffuse_texture"),
-        });
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &diffuse_tex,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            diffuse_rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimension.0),
-                rows_per_image: NonZeroU32::new(dimension.1),
-            },
-            tex_size,
-        );
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("temp buffer for texture"),
-            contents: &diffuse_rgba,
-            usage: wgpu::BufferUsage::COPY_DST,
+            ],
         });
+        Ok(DiffuseTexture {
+            texture,
+            view,
+            sampler,
+            bind_group,
+        })
     }
 }
@@ -0,0 +1,408 @@
+use crate::{
+    buffers::{
+        self,
+        generate::{self, Colors},
+        Instance, TexturedVertex, Vertex, VertexFormat,
+    },
+    camera::{Camera, CameraController},
+    model::Model,
+    pipeline_creator::BufferRelatedData,
+    shaders::{self, CompiledShader},
+    texture::{ColorSpace, Filter, Mipmaps, SamplerOptions, TextureData},
+};
+use anyhow::{ensure, Context, Result};
+use image::RgbaImage;
+use serde::Deserialize;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+use wgpu::{
+    Color, CompareFunction, Device, Face, PrimitiveTopology, TextureFormat, VertexBufferLayout,
+};
+
+// used when no scene file is given on the command line
+const DEFAULT_SCENES: &str = include_str!("scenes.toml");
+// textures of the built-in scenes are looked up next to it
+const DEFAULT_SCENES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Topology {
+    PointList,
+    LineList,
+    LineStrip,
+    #[default]
+    TriangleList,
+    TriangleStrip,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+enum CullMode {
+    None,
+    Front,
+    #[default]
+    Back,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum DepthCompare {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+enum DepthFormat {
+    #[default]
+    Depth32Float,
+    Depth24Plus,
+    Depth24PlusStencil8,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+}
+
+// shapes span 1 unit
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Mesh {
+    Pentagon,
+    Hexagon,
+    Quad,
+    Polygon { sides: u32 },
+    Grid { columns: u32, rows: u32 },
+    Cube,
+    UvSphere { segments: u32, rings: u32 },
+    Icosphere { subdivisions: u32 },
+    Cylinder { segments: u32 },
+    Torus { segments: u32, sides: u32 },
+}
+
+impl Mesh {
+    pub(crate) fn vertex_layout(self) -> VertexBufferLayout<'static> {
+        match self {
+            Mesh::Quad => TexturedVertex::desc(),
+            _ => Vertex::desc(),
+        }
+    }
+
+    /// Uploads the shape, in `color` when given instead of its own colors.
+    pub(crate) fn create_buffers(
+        self,
+        device: &Device,
+        color: Option<[f32; 3]>,
+    ) -> Result<BufferRelatedData> {
+        let colors = |own: Colors<'static>| color.map_or(own, Colors::Solid);
+        let (vertices, indices) = match self {
+            Mesh::Pentagon => generate::polygon(5, 0.5, colors(Colors::RGB)),
+            Mesh::Hexagon => generate::polygon(6, 0.5, colors(Colors::RGB)),
+            Mesh::Quad => {
+                return BufferRelatedData::new(
+                    device,
+                    buffers::QUAD_VERTICES,
+                    buffers::QUAD_INDICES,
+                )
+            }
+            Mesh::Polygon { sides } => generate::polygon(sides, 0.5, colors(Colors::RGB)),
+            Mesh::Grid { columns, rows } => {
+                generate::grid(columns, rows, 1.0, 1.0, colors(Colors::RGB))
+            }
+            Mesh::Cube => generate::cube(0.6, colors(Colors::Position)),
+            Mesh::UvSphere { segments, rings } => {
+                generate::uv_sphere(0.5, segments, rings, colors(Colors::Position))
+            }
+            Mesh::Icosphere { subdivisions } => {
+                generate::icosphere(0.5, subdivisions, colors(Colors::Position))
+            }
+            Mesh::Cylinder { segments } => {
+                generate::cylinder(0.4, 1.0, segments, colors(Colors::Position))
+            }
+            Mesh::Torus { segments, sides } => {
+                generate::torus(0.35, 0.15, segments, sides, colors(Colors::Position))
+            }
+        };
+        BufferRelatedData::new(device, &vertices, &indices)
+    }
+}
+
+#[derive(Deserialize)]
+struct RenderConfig {
+    name: String,
+    vertex_shader: String,
+    fragment_shader: String,
+    #[serde(default)]
+    topology: Topology,
+    #[serde(default)]
+    cull_mode: CullMode,
+    mesh: Option<Mesh>,
+    color: Option<[f32; 3]>,
+    model: Option<PathBuf>,
+    texture: Option<PathBuf>,
+    #[serde(default)]
+    mipmaps: Mipmaps,
+    #[serde(default)]
+    filter: Filter,
+    anisotropy: Option<u8>,
+    #[serde(default)]
+    sprites: Vec<PathBuf>,
+    clear_color: Option<[f64; 4]>,
+    camera: Option<CameraController>,
+    #[serde(default)]
+    projection: Projection,
+    depth: Option<DepthCompare>,
+    depth_format: Option<DepthFormat>,
+    instances: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SceneFile {
+    #[serde(default)]
+    render: Vec<RenderConfig>,
+}
+
+pub(crate) struct SceneTexture {
+    pub(crate) data: TextureData,
+    pub(crate) mipmaps: Mipmaps,
+    pub(crate) sampler: SamplerOptions,
+}
+
+/// One entry of the scene file, with its shaders resolved.
+pub(crate) struct SceneRender {
+    pub(crate) name: String,
+    pub(crate) vertex_shader: &'static CompiledShader,
+    pub(crate) fragment_shader: &'static CompiledShader,
+    pub(crate) topology: PrimitiveTopology,
+    pub(crate) cull_mode: Option<Face>,
+    pub(crate) mesh: Option<Mesh>,
+    // replaces the colors of the mesh
+    pub(crate) color: Option<[f32; 3]>,
+    // fitted into the window, drawn instead of a mesh
+    pub(crate) model: Option<Model>,
+    // bound to the group of the fragment shader
+    pub(crate) texture: Option<SceneTexture>,
+    // packed into one atlas and drawn as a row of quads
+    pub(crate) sprites: Vec<RgbaImage>,
+    pub(crate) clear_color: Option<Color>,
+    // for vertex shaders reading a camera
+    pub(crate) camera: Option<(Camera, CameraController)>,
+    // tested against a depth buffer when set
+    pub(crate) depth: Option<(TextureFormat, CompareFunction)>,
+    // how many copies of the mesh to draw as a crowd
+    pub(crate) instances: Option<u32>,
+}
+
+/// The renders to cycle through, read from a TOML file of `[[render]]` tables
+/// (see src/scenes.toml for the format).
+pub struct Scenes {
+    pub(crate) renders: Vec<SceneRender>,
+}
+
+impl Scenes {
+    /// Reads the scene file at `path`, or the built-in scenes when there is none.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let src =
+                    read_to_string(path).with_context(|| format!("cannot read {:?}", path))?;
+                let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                Self::parse(&src, dir).with_context(|| format!("invalid scene file {:?}", path))
+            }
+            None => Self::parse(DEFAULT_SCENES, Path::new(DEFAULT_SCENES_DIR))
+                .context("invalid built-in scenes"),
+        }
+    }
+
+    // texture paths are relative to `dir`
+    fn parse(src: &str, dir: &Path) -> Result<Self> {
+        let file: SceneFile = toml::from_str(src)?;
+        ensure!(!file.render.is_empty(), "no [[render]] defined");
+        let renders = file
+            .render
+            .into_iter()
+            .map(|render| {
+                let shader = |name: &str| {
+                    shaders::find(name)
+                        .with_context(|| format!("{}: unknown shader {}", render.name, name))
+                };
+                let vertex_shader = shader(&render.vertex_shader)?;
+                let fragment_shader = shader(&render.fragment_shader)?;
+                ensure!(
+                    render.mesh.is_none() || render.model.is_none(),
+                    "{}: mesh and model are exclusive",
+                    render.name
+                );
+                ensure!(
+                    render.color.is_none() || !matches!(render.mesh, None | Some(Mesh::Quad)),
+                    "{}: color needs a mesh with vertex colors",
+                    render.name
+                );
+                let mut model = match &render.model {
+                    Some(path) => {
+                        let mut model = Model::load(dir.join(path))
+                            .with_context(|| format!("{}: invalid model", render.name))?;
+                        model.fit(1.6);
+                        model.optimize();
+                        Some(model)
+                    }
+                    None => None,
+                };
+                let texture_data = match &render.texture {
+                    Some(path) => Some(
+                        TextureData::load(dir.join(path), ColorSpace::Srgb)
+                            .with_context(|| format!("{}: invalid texture", render.name))?,
+                    ),
+                    // a textured shader without a texture gets the model's first one
+                    None if !fragment_shader.bind_group_layouts.is_empty() => {
+                        model.as_mut().and_then(|model| {
+                            model
+                                .materials
+                                .iter_mut()
+                                .find_map(|material| material.diffuse_texture.take())
+                        })
+                    }
+                    None => None,
+                };
+                let texture = match texture_data {
+                    Some(data) => {
+                        ensure!(
+                            !fragment_shader.bind_group_layouts.is_empty(),
+                            "{}: {} has no bind group for the texture",
+                            render.name,
+                            fragment_shader.name
+                        );
+                        let anisotropy = render.anisotropy.unwrap_or(1);
+                        ensure!(
+                            anisotropy.is_power_of_two() && anisotropy <= 16,
+                            "{}: anisotropy must be 1, 2, 4, 8 or 16",
+                            render.name
+                        );
+                        Some(SceneTexture {
+                            data,
+                            mipmaps: render.mipmaps,
+                            sampler: SamplerOptions {
+                                filter: render.filter,
+                                anisotropy,
+                            },
+                        })
+                    }
+                    None => None,
+                };
+                if !render.sprites.is_empty() {
+                    ensure!(
+                        render.mesh.is_none() && model.is_none() && render.texture.is_none(),
+                        "{}: sprites bring their own mesh and texture",
+                        render.name
+                    );
+                    ensure!(
+                        !fragment_shader.bind_group_layouts.is_empty(),
+                        "{}: {} has no bind group for the sprites",
+                        render.name,
+                        fragment_shader.name
+                    );
+                }
+                let reads_camera = vertex_shader
+                    .bind_group_layouts
+                    .first()
+                    .is_some_and(|layout| !layout.entries.is_empty());
+                ensure!(
+                    reads_camera || render.camera.is_none(),
+                    "{}: {} reads no camera",
+                    render.name,
+                    vertex_shader.name
+                );
+                let camera = match render.projection {
+                    Projection::Perspective => Camera::perspective(),
+                    Projection::Orthographic => Camera::orthographic(),
+                };
+                ensure!(
+                    render.depth.is_some() || render.depth_format.is_none(),
+                    "{}: depth_format needs a depth compare function",
+                    render.name
+                );
+                if let Some(count) = render.instances {
+                    ensure!(count > 0, "{}: no instances to draw", render.name);
+                    let mesh = render.mesh.with_context(|| {
+                        format!("{}: instances need a mesh to draw", render.name)
+                    })?;
+                    ensure!(
+                        buffers::layouts_match(
+                            &[mesh.vertex_layout(), Instance::LAYOUT],
+                            vertex_shader
+                        ),
+                        "{}: {} doesn't read the mesh and instance attributes",
+                        render.name,
+                        vertex_shader.name
+                    );
+                }
+                let sprites = render
+                    .sprites
+                    .iter()
+                    .map(|path| {
+                        let path = dir.join(path);
+                        Ok(image::open(&path)
+                            .with_context(|| format!("{}: cannot load {:?}", render.name, path))?
+                            .to_rgba8())
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(SceneRender {
+                    vertex_shader,
+                    fragment_shader,
+                    topology: match render.topology {
+                        Topology::PointList => PrimitiveTopology::PointList,
+                        Topology::LineList => PrimitiveTopology::LineList,
+                        Topology::LineStrip => PrimitiveTopology::LineStrip,
+                        Topology::TriangleList => PrimitiveTopology::TriangleList,
+                        Topology::TriangleStrip => PrimitiveTopology::TriangleStrip,
+                    },
+                    cull_mode: match render.cull_mode {
+                        CullMode::None => None,
+                        CullMode::Front => Some(Face::Front),
+                        CullMode::Back => Some(Face::Back),
+                    },
+                    mesh: render.mesh,
+                    color: render.color,
+                    model,
+                    texture,
+                    sprites,
+                    clear_color: render.clear_color.map(|[r, g, b, a]| Color { r, g, b, a }),
+                    instances: render.instances,
+                    camera: reads_camera.then(|| (camera, render.camera.unwrap_or_default())),
+                    depth: render.depth.map(|compare| {
+                        let format = match render.depth_format.unwrap_or_default() {
+                            DepthFormat::Depth32Float => TextureFormat::Depth32Float,
+                            DepthFormat::Depth24Plus => TextureFormat::Depth24Plus,
+                            DepthFormat::Depth24PlusStencil8 => TextureFormat::Depth24PlusStencil8,
+                        };
+                        let compare = match compare {
+                            DepthCompare::Never => CompareFunction::Never,
+                            DepthCompare::Less => CompareFunction::Less,
+                            DepthCompare::Equal => CompareFunction::Equal,
+                            DepthCompare::LessEqual => CompareFunction::LessEqual,
+                            DepthCompare::Greater => CompareFunction::Greater,
+                            DepthCompare::NotEqual => CompareFunction::NotEqual,
+                            DepthCompare::GreaterEqual => CompareFunction::GreaterEqual,
+                            DepthCompare::Always => CompareFunction::Always,
+                        };
+                        (format, compare)
+                    }),
+                    name: render.name,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { renders })
+    }
+}
@@ -0,0 +1,133 @@
+// Shared by build.rs and, with the `hot-reload` feature, by the running app.
+use anyhow::{anyhow, bail, Context, Result};
+use naga::{
+    back::spv,
+    front::{glsl, wgsl},
+    valid::{Capabilities, ModuleInfo, ValidationFlags, Validator},
+    Module, ShaderStage,
+};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy)]
+pub(crate) enum ShaderKind {
+    Glsl(ShaderStage),
+    Wgsl,
+}
+
+impl ShaderKind {
+    pub(crate) fn from_path(src_path: &Path) -> Result<Self> {
+        let extension = src_path
+            .extension()
+            .context("file has no extension")?
+            .to_str()
+            .context("extension cannot be converted to &str")?;
+        Ok(match extension {
+            "vert" => ShaderKind::Glsl(ShaderStage::Vertex),
+            "frag" => ShaderKind::Glsl(ShaderStage::Fragment),
+            "comp" => ShaderKind::Glsl(ShaderStage::Compute),
+            "wgsl" => ShaderKind::Wgsl,
+            _ => bail!("unsupported shader: {:?}", src_path),
+        })
+    }
+}
+
+/// Inlines every `#include "file"` line, resolving the file relative to `include_dir`.
+/// Each included file is added to `includes`.
+pub(crate) fn preprocess(
+    src_path: &Path,
+    include_dir: &Path,
+    includes: &mut Vec<PathBuf>,
+) -> Result<String> {
+    preprocess_nested(src_path, include_dir, &mut Vec::new(), includes)
+}
+
+fn preprocess_nested(
+    src_path: &Path,
+    include_dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    includes: &mut Vec<PathBuf>,
+) -> Result<String> {
+    if stack.iter().any(|path| path == src_path) {
+        bail!("recursive include of {:?} from {:?}", src_path, stack);
+    }
+    let src = read_to_string(src_path).with_context(|| format!("cannot read {:?}", src_path))?;
+    stack.push(src_path.to_path_buf());
+    let mut out = String::with_capacity(src.len());
+    for line in src.lines() {
+        match line.trim_start().strip_prefix("#include") {
+            Some(rest) => {
+                let name = rest
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|rest| rest.strip_suffix('"'))
+                    .with_context(|| format!("malformed include in {:?}: {}", src_path, line))?;
+                let include_path = include_dir.join(name);
+                if !includes.contains(&include_path) {
+                    includes.push(include_path.clone());
+                }
+                out.push_str(&preprocess_nested(
+                    &include_path,
+                    include_dir,
+                    stack,
+                    includes,
+                )?);
+            }
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    stack.pop();
+    Ok(out)
+}
+
+fn parse(
+    src: &str,
+    src_path: &Path,
+    kind: ShaderKind,
+    defines: &HashMap<String, String>,
+) -> Result<Module> {
+    match kind {
+        ShaderKind::Glsl(stage) => {
+            let options = glsl::Options {
+                stage,
+                defines: defines
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+            };
+            glsl::Parser::default()
+                .parse(&options, src)
+                .map_err(|errors| anyhow!("{:?}: {:?}", src_path, errors))
+        }
+        ShaderKind::Wgsl => {
+            if !defines.is_empty() {
+                bail!("{:?}: defines are only supported for GLSL", src_path);
+            }
+            wgsl::parse_str(src)
+                .map_err(|error| anyhow!("{:?}: {}", src_path, error.emit_to_string(src)))
+        }
+    }
+}
+
+/// Parses and validates preprocessed shader source and translates it to SPIR-V.
+pub(crate) fn compile(
+    src: &str,
+    src_path: &Path,
+    kind: ShaderKind,
+    defines: &HashMap<String, String>,
+) -> Result<(Vec<u8>, Module, ModuleInfo)> {
+    let module = parse(src, src_path, kind, defines)?;
+    let info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .with_context(|| format!("{:?} failed validation", src_path))?;
+    let words = spv::write_vec(&module, &info, &spv::Options::default(), None)?;
+    let spirv = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .collect();
+    Ok((spirv, module, info))
+}
@@ -0,0 +1,70 @@
+use wgpu::{
+    util::make_spirv, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, Device,
+    ShaderFlags, ShaderModule, ShaderModuleDescriptor, VertexBufferLayout,
+};
+
+pub(crate) struct CompiledShader {
+    pub(crate) name: &'static str,
+    // absolute path of the source and the defines it was built with, for recompiling
+    #[cfg_attr(not(feature = "hot-reload"), allow(dead_code))]
+    pub(crate) source: &'static str,
+    #[cfg_attr(not(feature = "hot-reload"), allow(dead_code))]
+    pub(crate) defines: &'static [(&'static str, &'static str)],
+    pub(crate) spirv: &'static [u8],
+    // reflected by build.rs from the shader's inputs and resource bindings
+    pub(crate) vertex_layout: Option<VertexBufferLayout<'static>>,
+    // the names of the inputs, in the order of the `vertex_layout` attributes
+    pub(crate) vertex_inputs: &'static [&'static str],
+    pub(crate) bind_group_layouts: &'static [BindGroupLayoutDescriptor<'static>],
+}
+
+impl CompiledShader {
+    pub(crate) fn create_module(&self, device: &Device) -> ShaderModule {
+        device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some(self.name),
+            source: make_spirv(self.spirv),
+            flags: ShaderFlags::VALIDATION,
+        })
+    }
+}
+
+/// Looks a shader up by the name build.rs gave it, e.g. `challenge.vert`.
+pub(crate) fn find(name: &str) -> Option<&'static CompiledShader> {
+    ALL.iter().copied().find(|shader| shader.name == name)
+}
+
+/// Creates the bind group layouts for a pipeline made of `shaders`, merging the
+/// visibility of bindings that several stages share.
+pub(crate) fn create_bind_group_layouts(
+    device: &Device,
+    shaders: &[&CompiledShader],
+) -> Vec<BindGroupLayout> {
+    let group_count = shaders
+        .iter()
+        .map(|shader| shader.bind_group_layouts.len())
+        .max()
+        .unwrap_or(0);
+    (0..group_count)
+        .map(|group| {
+            let mut entries: Vec<BindGroupLayoutEntry> = Vec::new();
+            for layout in shaders
+                .iter()
+                .filter_map(|shader| shader.bind_group_layouts.get(group))
+            {
+                for entry in layout.entries {
+                    match entries.iter_mut().find(|e| e.binding == entry.binding) {
+                        Some(existing) => existing.visibility |= entry.visibility,
+                        None => entries.push(*entry),
+                    }
+                }
+            }
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("reflected bind group layout"),
+                entries: &entries,
+            })
+        })
+        .collect()
+}
+
+// one `CompiledShader` constant per shader in src/, e.g. `CHALLENGE_VERT`, and `ALL` of them
+include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
@@ -1,11 +1,27 @@
-use crate::pipeline_creator::*;
-use anyhow::Result;
+use crate::{
+    buffers,
+    camera::{CameraInput, CAMERA_GROUP},
+    capture,
+    pipeline_creator::*,
+    scene::Scenes,
+    shaders,
+    texture::{Filter, SamplerOptions},
+};
+use anyhow::{ensure, Context, Result};
+use image::RgbaImage;
 use log::info;
+use std::{
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use wgpu::{
-    BackendBit, Color, CommandEncoderDescriptor, Device, DeviceDescriptor, Features, IndexFormat,
-    Instance, Limits, Operations, PowerPreference, Queue, RenderPassColorAttachment,
-    RenderPassDescriptor, RequestAdapterOptions, Surface, SwapChain, SwapChainDescriptor,
-    TextureUsage,
+    Adapter, BackendBit, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindingResource, Color, CommandEncoder, CommandEncoderDescriptor, CompareFunction, Device,
+    DeviceDescriptor, DeviceType, Extent3d, Features, Instance, Limits, LoadOp, Operations,
+    PowerPreference, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, RequestAdapterOptions, Sampler, Surface, SwapChain,
+    SwapChainDescriptor, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsage,
+    TextureView, TextureViewDescriptor,
 };
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
@@ -27,12 +43,117 @@ impl Renders {
     }
 }
 
+// where the frame drawn into `State::frame` ends up
+enum RenderTarget {
+    Window {
+        surface: Surface,
+        swap_chain: SwapChain,
+        present: PresentBlit,
+    },
+    Offscreen,
+}
+
+// copies the frame to the swap chain, whose textures can't be read back for captures
+struct PresentBlit {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    bind_group: BindGroup,
+}
+
+impl PresentBlit {
+    fn new(device: &Device, format: TextureFormat, frame: &TextureView) -> Self {
+        let pipeline =
+            RenderPipelineBuilder::new("present blit", &shaders::BLIT_VERT, &shaders::BLIT_FRAG)
+                .cull_mode(None)
+                .build(device, format);
+        let layout =
+            shaders::create_bind_group_layouts(device, &[&shaders::BLIT_VERT, &shaders::BLIT_FRAG])
+                .swap_remove(0);
+        // the frame is as large as the swap chain, so texels map to pixels one to one
+        let sampler = SamplerOptions {
+            filter: Filter::Nearest,
+            anisotropy: 1,
+        }
+        .create_sampler(device, "present blit");
+        let bind_group = PresentBlit::create_bind_group(device, &layout, &sampler, frame);
+        Self {
+            pipeline,
+            layout,
+            sampler,
+            bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        sampler: &Sampler,
+        frame: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("present blit"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(frame),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    // the frame view changes whenever the window is resized
+    fn rebind(&mut self, device: &Device, frame: &TextureView) {
+        self.bind_group =
+            PresentBlit::create_bind_group(device, &self.layout, &self.sampler, frame);
+    }
+
+    fn draw(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Present pass"),
+            color_attachments: &[RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// sized like the swap chain, shared by the renders testing depth in its format
+struct DepthTarget {
+    format: TextureFormat,
+    // kept alive for the view
+    #[allow(dead_code)]
+    texture: Texture,
+    view: TextureView,
+}
+
 pub struct State {
-    surface: Surface,
+    target: RenderTarget,
+    // every frame is drawn here first, so the last one can be captured
+    frame: (Texture, TextureView),
+    sample_count: u32,
+    // drawn into and resolved to the target when multisampling
+    msaa_target: Option<(Texture, TextureView)>,
+    depth_targets: Vec<DepthTarget>,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: crate::hot_reload::ShaderWatcher,
     device: Device,
     queue: Queue,
     sc_desc: SwapChainDescriptor,
-    swap_chain: SwapChain,
     render: Renders,
     pub size: PhysicalSize<u32>,
     game_local: GameLocal,
@@ -41,15 +162,44 @@ pub struct State {
 struct GameLocal {
     mouse_input: MouseInputs,
     color: Color,
+    capture_requested: bool,
+    // animation time, advanced by `update`
+    time: Duration,
+    last_update: Instant,
+    // advances `time` instead of the wall clock when set
+    fixed_timestep: Option<Duration>,
 }
 
 struct MouseInputs {
     mouse_pointer_position: Option<PhysicalPosition<f64>>,
-    scroll: Option<MouseScrollDelta>,
+    // lines scrolled since the last update
+    scroll: f32,
+}
+
+// how far touchpads scroll for one line of a mouse wheel
+const PIXELS_PER_LINE: f64 = 20.0;
+
+// wheels report lines and touchpads pixels, positive away from the user
+fn scroll_lines(delta: MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, y) => y,
+        MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_LINE) as f32,
+    }
+}
+
+// wgpu can't ask the adapter which sample counts a format supports, so only the
+// 1 and 4 that WebGPU guarantees for every renderable format are accepted
+fn check_sample_count(sample_count: u32) -> Result<()> {
+    ensure!(
+        sample_count == 1 || sample_count == 4,
+        "{} samples per pixel are not supported, only 1 or 4",
+        sample_count
+    );
+    Ok(())
 }
 
 impl State {
-    pub(crate) async fn new(window: &Window) -> Self {
+    pub(crate) async fn new(window: &Window, scenes: &Scenes, sample_count: u32) -> Result<Self> {
         let size = window.inner_size();
         let instance = Instance::new(BackendBit::PRIMARY);
         let surface = unsafe { instance.create_surface(window) };
@@ -59,35 +209,253 @@ impl State {
                 compatible_surface: Some(&surface),
             })
             .await
-            .unwrap();
+            .context("no adapter available")?;
         info!("{:?}", adapter.get_info());
+        let (device, queue) = State::request_device(&adapter).await?;
+        let sc_desc = SwapChainDescriptor {
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: adapter
+                .get_swap_chain_preferred_format(&&surface)
+                .context("the adapter can't present to the window")?,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        check_sample_count(sample_count)?;
+        State::check_render_formats(&adapter, &sc_desc, scenes)?;
+        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        let frame = State::create_frame_target(&device, &sc_desc);
+        let present = PresentBlit::new(&device, sc_desc.format, &frame.1);
+        State::with_target(
+            RenderTarget::Window {
+                surface,
+                swap_chain,
+                present,
+            },
+            frame,
+            device,
+            queue,
+            sc_desc,
+            scenes,
+            sample_count,
+        )
+    }
+
+    /// Renders into an offscreen texture instead of a window, so no display is needed.
+    pub(crate) async fn new_headless(
+        size: PhysicalSize<u32>,
+        scenes: &Scenes,
+        sample_count: u32,
+    ) -> Result<Self> {
+        let instance = Instance::new(BackendBit::all());
+        let adapter = State::request_headless_adapter(&instance)
+            .await
+            .context("no adapter available, not even a software one")?;
+        info!("{:?}", adapter.get_info());
+        let (device, queue) = State::request_device(&adapter).await?;
+        let sc_desc = SwapChainDescriptor {
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: TextureFormat::Rgba8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        check_sample_count(sample_count)?;
+        State::check_render_formats(&adapter, &sc_desc, scenes)?;
+        let frame = State::create_frame_target(&device, &sc_desc);
+        State::with_target(
+            RenderTarget::Offscreen,
+            frame,
+            device,
+            queue,
+            sc_desc,
+            scenes,
+            sample_count,
+        )
+    }
+
+    // fails when the adapter can't render to the frame or a depth target at all
+    fn check_render_formats(
+        adapter: &Adapter,
+        sc_desc: &SwapChainDescriptor,
+        scenes: &Scenes,
+    ) -> Result<()> {
+        let depth_formats = scenes
+            .renders
+            .iter()
+            .filter_map(|render| render.depth.map(|(format, _)| format));
+        for format in std::iter::once(sc_desc.format).chain(depth_formats) {
+            ensure!(
+                adapter
+                    .get_texture_format_features(format)
+                    .allowed_usages
+                    .contains(TextureUsage::RENDER_ATTACHMENT),
+                "the adapter can't render to {:?}",
+                format
+            );
+        }
+        Ok(())
+    }
+
+    async fn request_headless_adapter(instance: &Instance) -> Option<Adapter> {
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                compatible_surface: None,
+            })
+            .await;
+        if adapter.is_some() {
+            return adapter;
+        }
+        // no hardware adapter, fall back to a software rasterizer (lavapipe, swiftshader, warp)
+        let mut adapters = instance
+            .enumerate_adapters(BackendBit::all())
+            .collect::<Vec<_>>();
+        let cpu = adapters
+            .iter()
+            .position(|adapter| adapter.get_info().device_type == DeviceType::Cpu);
+        match cpu {
+            Some(index) => Some(adapters.swap_remove(index)),
+            None => adapters.into_iter().next(),
+        }
+    }
+
+    async fn request_device(adapter: &Adapter) -> Result<(Device, Queue)> {
         let (device, queue) = adapter
             .request_device(
                 &DeviceDescriptor {
-                    features: Features::empty(),
+                    // compressed textures are decoded on the CPU when these are missing
+                    features: adapter.features()
+                        & (Features::TEXTURE_COMPRESSION_BC
+                            | Features::TEXTURE_COMPRESSION_ETC2
+                            | Features::TEXTURE_COMPRESSION_ASTC_LDR),
                     limits: Limits::default(),
                     label: None,
                 },
                 None,
             )
-            .await
-            .unwrap();
-        let sc_desc = SwapChainDescriptor {
+            .await?;
+        Ok((device, queue))
+    }
+
+    fn create_frame_target(
+        device: &Device,
+        sc_desc: &SwapChainDescriptor,
+    ) -> (Texture, TextureView) {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("frame target"),
+            size: Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: sc_desc.format,
+            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::COPY_SRC | TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    fn create_msaa_target(
+        device: &Device,
+        sc_desc: &SwapChainDescriptor,
+        sample_count: u32,
+    ) -> Option<(Texture, TextureView)> {
+        if sample_count == 1 {
+            return None;
+        }
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("multisampled target"),
+            size: Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format: sc_desc.format,
             usage: TextureUsage::RENDER_ATTACHMENT,
-            format: adapter.get_swap_chain_preferred_format(&&surface).unwrap(),
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
-        let render_pipelines = State::create_specific_render_pipelines(&device, &sc_desc);
-        Self {
-            surface,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Some((texture, view))
+    }
+
+    fn create_depth_targets(
+        device: &Device,
+        sc_desc: &SwapChainDescriptor,
+        sample_count: u32,
+        renders: &[SpecificRender],
+    ) -> Vec<DepthTarget> {
+        let mut formats = Vec::new();
+        for depth_stencil in renders
+            .iter()
+            .filter_map(|render| render.pipeline_builder.depth_stencil_state())
+        {
+            if !formats.contains(&depth_stencil.format) {
+                formats.push(depth_stencil.format);
+            }
+        }
+        formats
+            .into_iter()
+            .map(|format| {
+                let texture = device.create_texture(&TextureDescriptor {
+                    label: Some("depth target"),
+                    size: Extent3d {
+                        width: sc_desc.width,
+                        height: sc_desc.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage: TextureUsage::RENDER_ATTACHMENT,
+                });
+                let view = texture.create_view(&TextureViewDescriptor::default());
+                DepthTarget {
+                    format,
+                    texture,
+                    view,
+                }
+            })
+            .collect()
+    }
+
+    fn with_target(
+        target: RenderTarget,
+        frame: (Texture, TextureView),
+        device: Device,
+        queue: Queue,
+        sc_desc: SwapChainDescriptor,
+        scenes: &Scenes,
+        sample_count: u32,
+    ) -> Result<Self> {
+        let render_pipelines = State::create_specific_render_pipelines(
+            &device,
+            &queue,
+            &sc_desc,
+            sample_count,
+            scenes,
+        )?;
+        let msaa_target = State::create_msaa_target(&device, &sc_desc, sample_count);
+        let depth_targets =
+            State::create_depth_targets(&device, &sc_desc, sample_count, &render_pipelines);
+        Ok(Self {
+            sample_count,
+            msaa_target,
+            depth_targets,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: crate::hot_reload::ShaderWatcher::new(&device, &render_pipelines),
+            target,
+            frame,
             device,
             queue,
+            size: PhysicalSize::new(sc_desc.width, sc_desc.height),
             sc_desc,
-            swap_chain,
-            size,
             render: Renders {
                 renders: render_pipelines,
                 current_render: 0,
@@ -95,7 +463,7 @@ impl State {
             game_local: GameLocal {
                 mouse_input: MouseInputs {
                     mouse_pointer_position: None,
-                    scroll: None,
+                    scroll: 0.0,
                 },
                 color: Color {
                     r: 0.1,
@@ -103,27 +471,73 @@ impl State {
                     b: 0.3,
                     a: 1.0,
                 },
+                capture_requested: false,
+                time: Duration::ZERO,
+                last_update: Instant::now(),
+                fixed_timestep: None,
             },
-        }
+        })
     }
 
+    // minimized windows are 0x0, which no texture can be
+    fn minimized(&self) -> bool {
+        self.size.width == 0 || self.size.height == 0
+    }
+
+    /// Keeps the targets of the last size while the window is minimized.
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.size = new_size;
+        if self.minimized() {
+            return;
+        }
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        self.frame = State::create_frame_target(&self.device, &self.sc_desc);
+        if let RenderTarget::Window {
+            surface,
+            swap_chain,
+            present,
+        } = &mut self.target
+        {
+            *swap_chain = self.device.create_swap_chain(surface, &self.sc_desc);
+            present.rebind(&self.device, &self.frame.1);
+        }
+        self.msaa_target =
+            State::create_msaa_target(&self.device, &self.sc_desc, self.sample_count);
+        self.depth_targets = State::create_depth_targets(
+            &self.device,
+            &self.sc_desc,
+            self.sample_count,
+            &self.render.renders,
+        );
+    }
+
+    pub fn render_count(&self) -> usize {
+        self.render.renders.len()
+    }
+
+    pub fn render_name(&self) -> &str {
+        &self.render.renders[self.render.current_render].name
+    }
+
+    pub fn next_render(&mut self) {
+        self.render.next();
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::MouseWheel { delta, .. } => {
-                self.game_local.mouse_input.scroll = Some(*delta);
+                self.game_local.mouse_input.scroll += scroll_lines(*delta);
                 true
             }
             WindowEvent::CursorMoved { position, .. } => {
                 self.game_local.mouse_input.mouse_pointer_position = Some(*position);
                 true
             }
+            WindowEvent::CursorLeft { .. } => {
+                self.game_local.mouse_input.mouse_pointer_position = None;
+                true
+            }
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -133,52 +547,214 @@ impl State {
                     },
                 ..
             } => {
-                self.render.next();
+                self.next_render();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.game_local.capture_requested = true;
                 true
             }
             _ => false,
         }
     }
 
+    /// Advances animations by `timestep` on every `update` rather than by the time
+    /// that passed, so the frames rendered don't depend on how fast they were.
+    pub fn set_fixed_timestep(&mut self, timestep: Option<Duration>) {
+        self.game_local.fixed_timestep = timestep;
+    }
+
     pub fn update(&mut self) {
+        #[cfg(feature = "hot-reload")]
+        self.shader_watcher
+            .poll(&self.device, self.sc_desc.format, &mut self.render.renders);
+        let now = Instant::now();
+        let elapsed = now - std::mem::replace(&mut self.game_local.last_update, now);
+        // nothing moves while there is nothing to see
+        if self.minimized() {
+            return;
+        }
         if let Some(pos) = self.game_local.mouse_input.mouse_pointer_position {
             self.game_local.color.r = pos.x / self.size.width as f64;
             self.game_local.color.g = pos.y / self.size.height as f64;
         }
+        let dt = self.game_local.fixed_timestep.unwrap_or(elapsed);
+        self.game_local.time += dt;
+        let (width, height) = (self.sc_desc.width as f32, self.sc_desc.height as f32);
+        let input = CameraInput {
+            cursor: self
+                .game_local
+                .mouse_input
+                .mouse_pointer_position
+                .map(|pos| {
+                    [
+                        2.0 * pos.x as f32 / width - 1.0,
+                        1.0 - 2.0 * pos.y as f32 / height,
+                    ]
+                    .into()
+                }),
+            scroll: std::mem::take(&mut self.game_local.mouse_input.scroll),
+            dt: dt.as_secs_f32(),
+        };
+        let render = &mut self.render.renders[self.render.current_render];
+        if let Some(camera) = &mut render.camera {
+            camera.update(&self.queue, &input, width / height);
+        }
+        if let Some(instances) = &mut render.instances {
+            let crowd = buffers::crowd(instances.count, self.game_local.time.as_secs_f32());
+            instances.update(&self.device, &self.queue, &crowd);
+        }
     }
 
     pub fn render(&mut self) -> Result<()> {
-        let frame = self.swap_chain.get_current_frame()?.output;
-        let mut encoder = self
+        if self.minimized() {
+            return Ok(());
+        }
+        {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+            self.draw(&mut encoder, &self.frame.1);
+            // presented when dropped at the end of the block, after the submit
+            let _output = match &self.target {
+                RenderTarget::Window {
+                    swap_chain,
+                    present,
+                    ..
+                } => {
+                    let output = swap_chain.get_current_frame()?.output;
+                    present.draw(&mut encoder, &output.view);
+                    Some(output)
+                }
+                RenderTarget::Offscreen => None,
+            };
+            self.queue.submit(std::iter::once(encoder.finish()));
+        }
+        if self.game_local.capture_requested {
+            self.game_local.capture_requested = false;
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+            let path = format!("screenshot_{}.png", timestamp);
+            self.save_screenshot(&path)?;
+            info!("saved screenshot: {}", path);
+        }
+        Ok(())
+    }
+
+    /// Reads back the last frame `render` drew as RGBA.
+    pub fn capture_frame(&self) -> Result<RgbaImage> {
+        let encoder = self
             .device
             .create_command_encoder(&CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
+                label: Some("Capture Encoder"),
             });
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render pass"),
-                color_attachments: &[RenderPassColorAttachment {
-                    view: &frame.view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: wgpu::LoadOp::Clear(self.game_local.color),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
-            let render = &self.render.renders[self.render.current_render];
-            render_pass.set_pipeline(&render.render_pipeline);
-            if let Some(buf_related) = &render.buffer_related {
-                render_pass.set_vertex_buffer(0, buf_related.vertex_buffer.slice(..));
-                render_pass
-                    .set_index_buffer(buf_related.index_buffer.slice(..), IndexFormat::Uint16);
-                render_pass.draw_indexed(0..buf_related.num_indices, 0, 0..1);
-            } else {
-                render_pass.draw(0..3, 0..1);
+        capture::texture_to_image(
+            &self.device,
+            &self.queue,
+            encoder,
+            &self.frame.0,
+            self.sc_desc.format,
+            self.sc_desc.width,
+            self.sc_desc.height,
+        )
+    }
+
+    pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.capture_frame()?.save(path)?;
+        Ok(())
+    }
+
+    fn draw(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let render = &self.render.renders[self.render.current_render];
+        let depth_stencil_attachment =
+            render
+                .pipeline_builder
+                .depth_stencil_state()
+                .and_then(|depth_stencil| {
+                    let target = self
+                        .depth_targets
+                        .iter()
+                        .find(|target| target.format == depth_stencil.format)?;
+                    // cleared to the far plane of the comparison, so the first fragment passes
+                    let far = match depth_stencil.depth_compare {
+                        CompareFunction::Greater | CompareFunction::GreaterEqual => 0.0,
+                        _ => 1.0,
+                    };
+                    Some(RenderPassDepthStencilAttachment {
+                        view: &target.view,
+                        depth_ops: Some(Operations {
+                            load: LoadOp::Clear(far),
+                            store: true,
+                        }),
+                        stencil_ops: match depth_stencil.format {
+                            TextureFormat::Depth24PlusStencil8 => Some(Operations {
+                                load: LoadOp::Clear(0),
+                                store: true,
+                            }),
+                            _ => None,
+                        },
+                    })
+                });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Render pass"),
+            color_attachments: &[RenderPassColorAttachment {
+                view: self
+                    .msaa_target
+                    .as_ref()
+                    .map_or(view, |(_, msaa_view)| msaa_view),
+                resolve_target: self.msaa_target.as_ref().map(|_| view),
+                ops: Operations {
+                    load: LoadOp::Clear(render.clear_color.unwrap_or(self.game_local.color)),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment,
+        });
+        render_pass.set_pipeline(&render.render_pipeline);
+        if let Some(camera) = &render.camera {
+            render_pass.set_bind_group(CAMERA_GROUP, &camera.bind_group, &[]);
+        }
+        if let Some(texture) = &render.texture {
+            render_pass.set_bind_group(render.texture_group, &texture.bind_group, &[]);
+        }
+        let instance_count = match &render.instances {
+            Some(instances) => {
+                render_pass.set_vertex_buffer(1, instances.buffer.slice(..));
+                instances.count
             }
+            None => 1,
+        };
+        if let Some(buf_related) = &render.buffer_related {
+            render_pass.set_vertex_buffer(0, buf_related.vertex_buffer.slice(..));
+            render_pass
+                .set_index_buffer(buf_related.index_buffer.slice(..), buf_related.index_format);
+            render_pass.draw_indexed(0..buf_related.num_indices, 0, 0..instance_count);
+        } else {
+            render_pass.draw(0..3, 0..instance_count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_sample_counts_webgpu_guarantees() {
+        assert!(check_sample_count(1).is_ok());
+        assert!(check_sample_count(4).is_ok());
+        for sample_count in [0, 2, 3, 8, 16] {
+            let error = check_sample_count(sample_count).unwrap_err();
+            assert!(error.to_string().contains("only 1 or 4"), "{}", error);
         }
-        self.queue.submit(std::iter::once(encoder.finish()));
-        Ok(())
     }
 }
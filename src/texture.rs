@@ -0,0 +1,1004 @@
+use crate::{pipeline_creator::RenderPipelineBuilder, shaders};
+use anyhow::{bail, ensure, Context, Result};
+use image::{
+    codecs::{
+        dxt::{DXTVariant, DxtDecoder},
+        hdr::HdrDecoder,
+    },
+    DynamicImage, ImageFormat,
+};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    fs,
+    num::{NonZeroU32, NonZeroU8},
+    path::Path,
+};
+use wgpu::{
+    AddressMode, BindGroupDescriptor, BindGroupEntry, BindingResource, Color,
+    CommandEncoderDescriptor, Device, Extent3d, FilterMode, ImageCopyTexture, ImageDataLayout,
+    LoadOp, Operations, Origin3d, Queue, RenderPassColorAttachment, RenderPassDescriptor, Sampler,
+    SamplerDescriptor, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsage,
+    TextureViewDescriptor,
+};
+
+const KTX2_IDENTIFIER: &[u8] = &[
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const DDS_MAGIC: &[u8] = b"DDS ";
+
+/// How 8 bit color data is meant to be read. Containers that name their own format
+/// (KTX2, DX10 DDS) and float data ignore it.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+/// How the mip chain of an image without one is made.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Mipmaps {
+    #[default]
+    Gpu,
+    Cpu,
+    None,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Filter {
+    Nearest,
+    Bilinear,
+    #[default]
+    Trilinear,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct SamplerOptions {
+    pub(crate) filter: Filter,
+    // 1 turns it off, otherwise a power of two up to 16
+    pub(crate) anisotropy: u8,
+}
+
+impl SamplerOptions {
+    pub(crate) fn create_sampler(&self, device: &Device, label: &str) -> Sampler {
+        let (filter, mipmap_filter) = match self.filter {
+            Filter::Nearest => (FilterMode::Nearest, FilterMode::Nearest),
+            Filter::Bilinear => (FilterMode::Linear, FilterMode::Nearest),
+            Filter::Trilinear => (FilterMode::Linear, FilterMode::Linear),
+        };
+        device.create_sampler(&SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter,
+            anisotropy_clamp: NonZeroU8::new(self.anisotropy).filter(|clamp| clamp.get() > 1),
+            ..Default::default()
+        })
+    }
+}
+
+/// Pixels decoded from an image file, ready to be uploaded.
+pub(crate) struct TextureData {
+    pub(crate) label: String,
+    pub(crate) format: TextureFormat,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    // tightly packed rows of texels (or blocks), largest level first
+    pub(crate) levels: Vec<Vec<u8>>,
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    let word = bytes.get(offset..offset + 4).context("file is truncated")?;
+    Ok(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<u64> {
+    Ok(u32_at(bytes, offset)? as u64 | (u32_at(bytes, offset + 4)? as u64) << 32)
+}
+
+// size of a mip level rounded up to whole blocks, the bytes in one row of blocks
+// and the number of rows
+fn level_layout(
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    level: u32,
+) -> (Extent3d, u32, u32) {
+    let info = format.describe();
+    let block_width = info.block_dimensions.0 as u32;
+    let block_height = info.block_dimensions.1 as u32;
+    let blocks_wide = (width >> level).max(1).div_ceil(block_width);
+    let blocks_high = (height >> level).max(1).div_ceil(block_height);
+    let extent = Extent3d {
+        width: blocks_wide * block_width,
+        height: blocks_high * block_height,
+        depth_or_array_layers: 1,
+    };
+    (extent, blocks_wide * info.block_size as u32, blocks_high)
+}
+
+/// The number of levels in a full mip chain, down to 1x1.
+pub(crate) fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+fn level_len(format: TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (_, bytes_per_row, rows) = level_layout(format, width, height, level);
+    bytes_per_row as usize * rows as usize
+}
+
+// IEEE half precision, rounded to nearest
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = (mantissa | 0x80_0000) >> (1 - exponent);
+        return sign | ((mantissa + 0x1000) >> 13) as u16;
+    }
+    // a rounding carry out of the mantissa correctly bumps the exponent
+    sign | (((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+}
+
+// the sRGB transfer function, undone
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// the next mip level of 8 bit texels with `channels` bytes, each texel the average
+// of 2x2 texels of `pixels`; sRGB colors are averaged as light, like on the GPU, and
+// the last row or column of odd sizes is left out
+fn downsample(pixels: &[u8], width: u32, height: u32, channels: usize, srgb: bool) -> Vec<u8> {
+    let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+    let is_color = |channel| srgb && channel < 3;
+    let mut next = Vec::with_capacity(next_width as usize * next_height as usize * channels);
+    for y in 0..next_height {
+        for x in 0..next_width {
+            for channel in 0..channels {
+                let sum = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .map(|&(dx, dy)| {
+                        let texel_x = (2 * x + dx).min(width - 1) as usize;
+                        let texel_y = (2 * y + dy).min(height - 1) as usize;
+                        let byte =
+                            pixels[(texel_y * width as usize + texel_x) * channels + channel];
+                        let value = byte as f32 / 255.0;
+                        if is_color(channel) {
+                            srgb_to_linear(value)
+                        } else {
+                            value
+                        }
+                    })
+                    .sum::<f32>();
+                let value = if is_color(channel) {
+                    linear_to_srgb(sum / 4.0)
+                } else {
+                    sum / 4.0
+                };
+                next.push((value * 255.0).round() as u8);
+            }
+        }
+    }
+    next
+}
+
+fn rgba16_float(pixels: impl Iterator<Item = f32>) -> Vec<u8> {
+    pixels
+        .flat_map(|value| f16_bits(value).to_le_bytes().to_vec())
+        .collect()
+}
+
+fn vk_format(vk_format: u32) -> Result<TextureFormat> {
+    Ok(match vk_format {
+        9 => TextureFormat::R8Unorm,
+        16 => TextureFormat::Rg8Unorm,
+        37 => TextureFormat::Rgba8Unorm,
+        43 => TextureFormat::Rgba8UnormSrgb,
+        44 => TextureFormat::Bgra8Unorm,
+        50 => TextureFormat::Bgra8UnormSrgb,
+        97 => TextureFormat::Rgba16Float,
+        109 => TextureFormat::Rgba32Float,
+        122 => TextureFormat::Rg11b10Float,
+        131 | 133 => TextureFormat::Bc1RgbaUnorm,
+        132 | 134 => TextureFormat::Bc1RgbaUnormSrgb,
+        135 => TextureFormat::Bc2RgbaUnorm,
+        136 => TextureFormat::Bc2RgbaUnormSrgb,
+        137 => TextureFormat::Bc3RgbaUnorm,
+        138 => TextureFormat::Bc3RgbaUnormSrgb,
+        139 => TextureFormat::Bc4RUnorm,
+        140 => TextureFormat::Bc4RSnorm,
+        141 => TextureFormat::Bc5RgUnorm,
+        142 => TextureFormat::Bc5RgSnorm,
+        143 => TextureFormat::Bc6hRgbUfloat,
+        144 => TextureFormat::Bc6hRgbSfloat,
+        145 => TextureFormat::Bc7RgbaUnorm,
+        146 => TextureFormat::Bc7RgbaUnormSrgb,
+        147 => TextureFormat::Etc2RgbUnorm,
+        148 => TextureFormat::Etc2RgbUnormSrgb,
+        149 => TextureFormat::Etc2RgbA1Unorm,
+        150 => TextureFormat::Etc2RgbA1UnormSrgb,
+        151 => TextureFormat::Etc2RgbA8Unorm,
+        152 => TextureFormat::Etc2RgbA8UnormSrgb,
+        153 => TextureFormat::EacRUnorm,
+        154 => TextureFormat::EacRSnorm,
+        155 => TextureFormat::EtcRgUnorm,
+        156 => TextureFormat::EtcRgSnorm,
+        157 => TextureFormat::Astc4x4RgbaUnorm,
+        158 => TextureFormat::Astc4x4RgbaUnormSrgb,
+        159 => TextureFormat::Astc5x4RgbaUnorm,
+        160 => TextureFormat::Astc5x4RgbaUnormSrgb,
+        161 => TextureFormat::Astc5x5RgbaUnorm,
+        162 => TextureFormat::Astc5x5RgbaUnormSrgb,
+        163 => TextureFormat::Astc6x5RgbaUnorm,
+        164 => TextureFormat::Astc6x5RgbaUnormSrgb,
+        165 => TextureFormat::Astc6x6RgbaUnorm,
+        166 => TextureFormat::Astc6x6RgbaUnormSrgb,
+        167 => TextureFormat::Astc8x5RgbaUnorm,
+        168 => TextureFormat::Astc8x5RgbaUnormSrgb,
+        169 => TextureFormat::Astc8x6RgbaUnorm,
+        170 => TextureFormat::Astc8x6RgbaUnormSrgb,
+        171 => TextureFormat::Astc8x8RgbaUnorm,
+        172 => TextureFormat::Astc8x8RgbaUnormSrgb,
+        0 => bail!("KTX2 without a format (Basis Universal) is not supported"),
+        _ => bail!("unsupported KTX2 format {}", vk_format),
+    })
+}
+
+fn dxgi_format(dxgi_format: u32) -> Result<TextureFormat> {
+    Ok(match dxgi_format {
+        2 => TextureFormat::Rgba32Float,
+        10 => TextureFormat::Rgba16Float,
+        28 => TextureFormat::Rgba8Unorm,
+        29 => TextureFormat::Rgba8UnormSrgb,
+        49 => TextureFormat::Rg8Unorm,
+        61 => TextureFormat::R8Unorm,
+        71 => TextureFormat::Bc1RgbaUnorm,
+        72 => TextureFormat::Bc1RgbaUnormSrgb,
+        74 => TextureFormat::Bc2RgbaUnorm,
+        75 => TextureFormat::Bc2RgbaUnormSrgb,
+        77 => TextureFormat::Bc3RgbaUnorm,
+        78 => TextureFormat::Bc3RgbaUnormSrgb,
+        80 => TextureFormat::Bc4RUnorm,
+        81 => TextureFormat::Bc4RSnorm,
+        83 => TextureFormat::Bc5RgUnorm,
+        84 => TextureFormat::Bc5RgSnorm,
+        87 => TextureFormat::Bgra8Unorm,
+        91 => TextureFormat::Bgra8UnormSrgb,
+        95 => TextureFormat::Bc6hRgbUfloat,
+        96 => TextureFormat::Bc6hRgbSfloat,
+        98 => TextureFormat::Bc7RgbaUnorm,
+        99 => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => bail!("unsupported DXGI format {}", dxgi_format),
+    })
+}
+
+impl TextureData {
+    pub(crate) fn load<P: AsRef<Path>>(path: P, color_space: ColorSpace) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("cannot read {:?}", path))?;
+        Self::from_memory(&bytes, &path.display().to_string(), color_space)
+            .with_context(|| format!("cannot load {:?}", path))
+    }
+
+    /// Picks the decoder from the file contents: KTX2, DDS, Radiance HDR or anything
+    /// the `image` crate reads (PNG, JPEG, ...).
+    pub(crate) fn from_memory(bytes: &[u8], label: &str, color_space: ColorSpace) -> Result<Self> {
+        if bytes.starts_with(KTX2_IDENTIFIER) {
+            return Self::from_ktx2(bytes, label);
+        }
+        if bytes.starts_with(DDS_MAGIC) {
+            return Self::from_dds(bytes, label, color_space);
+        }
+        if let Ok(ImageFormat::Hdr) = image::guess_format(bytes) {
+            return Self::from_hdr(bytes, label);
+        }
+        Ok(Self::from_image(
+            &image::load_from_memory(bytes)?,
+            label,
+            color_space,
+        ))
+    }
+
+    /// 8 bit images become RGBA8, except linear greyscale which stays single channel.
+    /// 16 bit images become RGBA16 float to keep their precision, with sRGB colors
+    /// decoded up front as there is no sRGB float format.
+    pub(crate) fn from_image(image: &DynamicImage, label: &str, color_space: ColorSpace) -> Self {
+        let (format, width, height, pixels) = match image {
+            DynamicImage::ImageLuma8(luma) if color_space == ColorSpace::Linear => (
+                TextureFormat::R8Unorm,
+                luma.width(),
+                luma.height(),
+                luma.as_raw().clone(),
+            ),
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => {
+                let rgba = image.to_rgba16();
+                let pixels = rgba16_float(rgba.as_raw().iter().enumerate().map(|(i, value)| {
+                    let value = *value as f32 / 65535.0;
+                    // alpha is linear either way
+                    match color_space {
+                        ColorSpace::Srgb if i % 4 != 3 => srgb_to_linear(value),
+                        _ => value,
+                    }
+                }));
+                (
+                    TextureFormat::Rgba16Float,
+                    rgba.width(),
+                    rgba.height(),
+                    pixels,
+                )
+            }
+            _ => {
+                let rgba = image.to_rgba8();
+                let format = match color_space {
+                    ColorSpace::Srgb => TextureFormat::Rgba8UnormSrgb,
+                    ColorSpace::Linear => TextureFormat::Rgba8Unorm,
+                };
+                (format, rgba.width(), rgba.height(), rgba.into_raw())
+            }
+        };
+        Self {
+            label: label.to_string(),
+            format,
+            width,
+            height,
+            levels: vec![pixels],
+        }
+    }
+
+    fn from_hdr(bytes: &[u8], label: &str) -> Result<Self> {
+        let decoder = HdrDecoder::new(bytes)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        let pixels = rgba16_float(
+            pixels
+                .iter()
+                .flat_map(|pixel| vec![pixel[0], pixel[1], pixel[2], 1.0]),
+        );
+        Ok(Self {
+            label: label.to_string(),
+            format: TextureFormat::Rgba16Float,
+            width: metadata.width,
+            height: metadata.height,
+            levels: vec![pixels],
+        })
+    }
+
+    fn from_ktx2(bytes: &[u8], label: &str) -> Result<Self> {
+        let format = vk_format(u32_at(bytes, 12)?)?;
+        let width = u32_at(bytes, 20)?;
+        let height = u32_at(bytes, 24)?.max(1);
+        ensure!(
+            u32_at(bytes, 28)? <= 1 && u32_at(bytes, 32)? <= 1 && u32_at(bytes, 36)? == 1,
+            "only single 2D KTX2 images are supported"
+        );
+        ensure!(
+            u32_at(bytes, 44)? == 0,
+            "supercompressed KTX2 is not supported"
+        );
+        let level_count = u32_at(bytes, 40)?.max(1);
+        let levels = (0..level_count)
+            .map(|level| {
+                // level index entries start after the 80 byte header
+                let entry = 80 + 24 * level as usize;
+                let offset = u64_at(bytes, entry)? as usize;
+                let len = u64_at(bytes, entry + 8)? as usize;
+                ensure!(
+                    len == level_len(format, width, height, level),
+                    "level {} has {} bytes, expected {}",
+                    level,
+                    len,
+                    level_len(format, width, height, level)
+                );
+                let data = bytes
+                    .get(offset..offset + len)
+                    .context("file is truncated")?;
+                Ok(data.to_vec())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            label: label.to_string(),
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    fn from_dds(bytes: &[u8], label: &str, color_space: ColorSpace) -> Result<Self> {
+        let srgb = color_space == ColorSpace::Srgb;
+        let height = u32_at(bytes, 12)?;
+        let width = u32_at(bytes, 16)?;
+        let level_count = u32_at(bytes, 28)?.max(1);
+        ensure!(
+            u32_at(bytes, 112)? & 0x200 == 0,
+            "DDS cube maps are not supported"
+        );
+        let pixel_flags = u32_at(bytes, 80)?;
+        let four_cc = bytes.get(84..88).context("file is truncated")?;
+        let (format, mut offset) = if pixel_flags & 0x4 != 0 {
+            match four_cc {
+                b"DX10" => {
+                    ensure!(
+                        u32_at(bytes, 132)? == 3 && u32_at(bytes, 140)? == 1,
+                        "only single 2D DDS images are supported"
+                    );
+                    (dxgi_format(u32_at(bytes, 128)?)?, 148)
+                }
+                b"DXT1" if srgb => (TextureFormat::Bc1RgbaUnormSrgb, 128),
+                b"DXT1" => (TextureFormat::Bc1RgbaUnorm, 128),
+                b"DXT2" | b"DXT3" if srgb => (TextureFormat::Bc2RgbaUnormSrgb, 128),
+                b"DXT2" | b"DXT3" => (TextureFormat::Bc2RgbaUnorm, 128),
+                b"DXT4" | b"DXT5" if srgb => (TextureFormat::Bc3RgbaUnormSrgb, 128),
+                b"DXT4" | b"DXT5" => (TextureFormat::Bc3RgbaUnorm, 128),
+                b"ATI1" | b"BC4U" => (TextureFormat::Bc4RUnorm, 128),
+                b"ATI2" | b"BC5U" => (TextureFormat::Bc5RgUnorm, 128),
+                _ => bail!(
+                    "unsupported DDS format {}",
+                    String::from_utf8_lossy(four_cc)
+                ),
+            }
+        } else {
+            let masks = (u32_at(bytes, 88)?, u32_at(bytes, 92)?, u32_at(bytes, 100)?);
+            let format = match (masks, srgb) {
+                ((32, 0xff, 0xff_0000), true) => TextureFormat::Rgba8UnormSrgb,
+                ((32, 0xff, 0xff_0000), false) => TextureFormat::Rgba8Unorm,
+                ((32, 0xff_0000, 0xff), true) => TextureFormat::Bgra8UnormSrgb,
+                ((32, 0xff_0000, 0xff), false) => TextureFormat::Bgra8Unorm,
+                _ => bail!("only 32 bit RGBA and BGRA uncompressed DDS are supported"),
+            };
+            (format, 128)
+        };
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let len = level_len(format, width, height, level);
+            let data = bytes
+                .get(offset..offset + len)
+                .context("file is truncated")?;
+            levels.push(data.to_vec());
+            offset += len;
+        }
+        Ok(Self {
+            label: label.to_string(),
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    // BC1-3 can still be decoded here when the adapter can't sample them; mips are dropped
+    fn decompressed(&self) -> Result<Self> {
+        let (variant, srgb) = match self.format {
+            TextureFormat::Bc1RgbaUnorm => (DXTVariant::DXT1, false),
+            TextureFormat::Bc1RgbaUnormSrgb => (DXTVariant::DXT1, true),
+            TextureFormat::Bc2RgbaUnorm => (DXTVariant::DXT3, false),
+            TextureFormat::Bc2RgbaUnormSrgb => (DXTVariant::DXT3, true),
+            TextureFormat::Bc3RgbaUnorm => (DXTVariant::DXT5, false),
+            TextureFormat::Bc3RgbaUnormSrgb => (DXTVariant::DXT5, true),
+            format => bail!(
+                "{}: {:?} needs {:?}, which the adapter doesn't support",
+                self.label,
+                format,
+                format.describe().required_features
+            ),
+        };
+        let (extent, _, _) = level_layout(self.format, self.width, self.height, 0);
+        let decoder = DxtDecoder::new(&self.levels[0][..], extent.width, extent.height, variant)?;
+        let image = DynamicImage::from_decoder(decoder)?.crop_imm(0, 0, self.width, self.height);
+        let color_space = if srgb {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        };
+        Ok(Self::from_image(&image, &self.label, color_space))
+    }
+
+    /// Fills in the rest of the mip chain on the CPU with a box filter, for formats
+    /// the GPU can't render to and for testing.
+    pub(crate) fn with_mipmaps(&self) -> Result<Self> {
+        let (channels, srgb) = match self.format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Bgra8Unorm => (4, false),
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Bgra8UnormSrgb => (4, true),
+            TextureFormat::R8Unorm => (1, false),
+            format => bail!("{}: no CPU mipmaps for {:?}", self.label, format),
+        };
+        ensure!(
+            self.levels[0].len() == self.width as usize * self.height as usize * channels,
+            "{}: level has the wrong size",
+            self.label
+        );
+        let level_count = mip_level_count(self.width, self.height);
+        let mut levels = vec![self.levels[0].clone()];
+        for level in 1..level_count {
+            let previous = &levels[level as usize - 1];
+            let pixels = downsample(
+                previous,
+                (self.width >> (level - 1)).max(1),
+                (self.height >> (level - 1)).max(1),
+                channels,
+                srgb,
+            );
+            levels.push(pixels);
+        }
+        Ok(Self {
+            label: self.label.clone(),
+            format: self.format,
+            width: self.width,
+            height: self.height,
+            levels,
+        })
+    }
+
+    /// Creates a sampled texture with every level uploaded, decoding block compressed
+    /// data first if the device can't sample it. Images without mips of their own get
+    /// them generated as `mipmaps` asks, on the CPU when the GPU can't render the format.
+    pub(crate) fn upload(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        mipmaps: Mipmaps,
+    ) -> Result<Texture> {
+        let features = self.format.describe().required_features;
+        let decoded;
+        let mut data = if device.features().contains(features) {
+            self
+        } else {
+            decoded = self.decompressed()?;
+            &decoded
+        };
+        let format_features = data.format.describe().guaranteed_format_features;
+        let on_gpu = mipmaps == Mipmaps::Gpu
+            && data.levels.len() == 1
+            && format_features
+                .allowed_usages
+                .contains(TextureUsage::RENDER_ATTACHMENT)
+            && format_features.filterable;
+        let with_mipmaps;
+        if mipmaps != Mipmaps::None && !on_gpu && data.levels.len() == 1 {
+            match data.with_mipmaps() {
+                Ok(generated) => {
+                    with_mipmaps = generated;
+                    data = &with_mipmaps;
+                }
+                Err(e) if mipmaps == Mipmaps::Gpu => warn!("{:?}, not generating mipmaps", e),
+                Err(e) => return Err(e),
+            }
+        }
+        let level_count = if on_gpu {
+            mip_level_count(data.width, data.height)
+        } else {
+            data.levels.len() as u32
+        };
+        let mut usage = TextureUsage::SAMPLED | TextureUsage::COPY_DST;
+        if on_gpu {
+            usage |= TextureUsage::RENDER_ATTACHMENT;
+        }
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(&data.label),
+            size: Extent3d {
+                width: data.width,
+                height: data.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: data.format,
+            usage,
+        });
+        for (level, pixels) in data.levels.iter().enumerate() {
+            let (extent, bytes_per_row, _) =
+                level_layout(data.format, data.width, data.height, level as u32);
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: Origin3d::ZERO,
+                },
+                pixels,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+                extent,
+            );
+        }
+        if on_gpu {
+            generate_mipmaps(device, queue, &texture, data.format, level_count);
+        }
+        Ok(texture)
+    }
+}
+
+// renders each level from the one above it with a linear filter
+fn generate_mipmaps(
+    device: &Device,
+    queue: &Queue,
+    texture: &Texture,
+    format: TextureFormat,
+    level_count: u32,
+) {
+    let pipeline =
+        RenderPipelineBuilder::new("mipmap blit", &shaders::BLIT_VERT, &shaders::BLIT_FRAG)
+            .cull_mode(None)
+            .build(device, format);
+    let layouts =
+        shaders::create_bind_group_layouts(device, &[&shaders::BLIT_VERT, &shaders::BLIT_FRAG]);
+    let sampler = SamplerOptions {
+        filter: Filter::Bilinear,
+        anisotropy: 1,
+    }
+    .create_sampler(device, "mipmap blit");
+    let views = (0..level_count)
+        .map(|level| {
+            texture.create_view(&TextureViewDescriptor {
+                label: Some("mipmap level"),
+                base_mip_level: level,
+                mip_level_count: NonZeroU32::new(1),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+    for level in 1..level_count as usize {
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("mipmap blit"),
+            layout: &layouts[0],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&views[level - 1]),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Mipmap pass"),
+            color_attachments: &[RenderPassColorAttachment {
+                view: &views[level],
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, ImageBuffer, Rgb, RgbImage, Rgba};
+
+    fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // a single 2D image with the levels stored after the level index
+    fn ktx2(vk_format: u32, width: u32, height: u32, levels: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = vec![0; 80 + 24 * levels.len()];
+        bytes[..12].copy_from_slice(KTX2_IDENTIFIER);
+        put_u32(&mut bytes, 12, vk_format);
+        put_u32(&mut bytes, 20, width);
+        put_u32(&mut bytes, 24, height);
+        put_u32(&mut bytes, 36, 1);
+        put_u32(&mut bytes, 40, levels.len() as u32);
+        for (level, data) in levels.iter().enumerate() {
+            let entry = 80 + 24 * level;
+            let offset = bytes.len() as u64;
+            put_u64(&mut bytes, entry, offset);
+            put_u64(&mut bytes, entry + 8, data.len() as u64);
+            put_u64(&mut bytes, entry + 16, data.len() as u64);
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn dds(width: u32, height: u32, pixel_flags: u32, four_cc: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0; 128];
+        bytes[..4].copy_from_slice(DDS_MAGIC);
+        put_u32(&mut bytes, 4, 124);
+        put_u32(&mut bytes, 12, height);
+        put_u32(&mut bytes, 16, width);
+        put_u32(&mut bytes, 28, 1);
+        put_u32(&mut bytes, 76, 32);
+        put_u32(&mut bytes, 80, pixel_flags);
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes
+    }
+
+    #[test]
+    fn reads_ktx2_levels() {
+        let level0 = [1; 16];
+        let level1 = [2; 4];
+        let bytes = ktx2(43, 2, 2, &[&level0, &level1]);
+        let data = TextureData::from_memory(&bytes, "ktx2", ColorSpace::Linear).unwrap();
+        assert!(data.format == TextureFormat::Rgba8UnormSrgb);
+        assert_eq!((data.width, data.height), (2, 2));
+        assert_eq!(data.levels, vec![level0.to_vec(), level1.to_vec()]);
+    }
+
+    #[test]
+    fn reads_block_compressed_ktx2() {
+        // a 5x3 BC1 image takes 2x1 blocks of 8 bytes
+        let bytes = ktx2(131, 5, 3, &[&[7; 16]]);
+        let data = TextureData::from_memory(&bytes, "ktx2", ColorSpace::Srgb).unwrap();
+        assert!(data.format == TextureFormat::Bc1RgbaUnorm);
+        assert_eq!(data.levels[0].len(), 16);
+    }
+
+    #[test]
+    fn rejects_malformed_ktx2() {
+        let wrong_length = ktx2(37, 2, 2, &[&[0; 12]]);
+        assert!(TextureData::from_memory(&wrong_length, "ktx2", ColorSpace::Srgb).is_err());
+        let mut supercompressed = ktx2(37, 1, 1, &[&[0; 4]]);
+        put_u32(&mut supercompressed, 44, 1);
+        assert!(TextureData::from_memory(&supercompressed, "ktx2", ColorSpace::Srgb).is_err());
+        let mut array = ktx2(37, 1, 1, &[&[0; 4]]);
+        put_u32(&mut array, 32, 2);
+        assert!(TextureData::from_memory(&array, "ktx2", ColorSpace::Srgb).is_err());
+        let basis = ktx2(0, 1, 1, &[&[0; 4]]);
+        assert!(TextureData::from_memory(&basis, "ktx2", ColorSpace::Srgb).is_err());
+        let truncated = &ktx2(37, 2, 2, &[&[0; 16]])[..100];
+        assert!(TextureData::from_memory(truncated, "ktx2", ColorSpace::Srgb).is_err());
+    }
+
+    #[test]
+    fn reads_dds_four_cc_in_the_requested_color_space() {
+        let mut bytes = dds(4, 4, 0x4, b"DXT1");
+        bytes.extend_from_slice(&[3; 8]);
+        let srgb = TextureData::from_memory(&bytes, "dds", ColorSpace::Srgb).unwrap();
+        assert!(srgb.format == TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(srgb.levels, vec![vec![3; 8]]);
+        let linear = TextureData::from_memory(&bytes, "dds", ColorSpace::Linear).unwrap();
+        assert!(linear.format == TextureFormat::Bc1RgbaUnorm);
+    }
+
+    #[test]
+    fn reads_uncompressed_dds_by_channel_masks() {
+        let mut bytes = dds(1, 1, 0x41, &[0; 4]);
+        put_u32(&mut bytes, 88, 32);
+        put_u32(&mut bytes, 92, 0xff_0000);
+        put_u32(&mut bytes, 96, 0xff00);
+        put_u32(&mut bytes, 100, 0xff);
+        bytes.extend_from_slice(&[10, 20, 30, 40]);
+        let data = TextureData::from_memory(&bytes, "dds", ColorSpace::Linear).unwrap();
+        assert!(data.format == TextureFormat::Bgra8Unorm);
+        assert_eq!(data.levels, vec![vec![10, 20, 30, 40]]);
+        put_u32(&mut bytes, 88, 24);
+        assert!(TextureData::from_memory(&bytes, "dds", ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn reads_dx10_dds_with_mip_levels() {
+        let mut bytes = dds(2, 2, 0x4, b"DX10");
+        put_u32(&mut bytes, 28, 2);
+        bytes.extend_from_slice(&[0; 20]);
+        put_u32(&mut bytes, 128, 29);
+        put_u32(&mut bytes, 132, 3);
+        put_u32(&mut bytes, 140, 1);
+        bytes.extend((0..20).map(|byte| byte as u8));
+        let data = TextureData::from_memory(&bytes, "dds", ColorSpace::Linear).unwrap();
+        assert!(data.format == TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(data.levels[0], (0..16).collect::<Vec<u8>>());
+        assert_eq!(data.levels[1], vec![16, 17, 18, 19]);
+        put_u32(&mut bytes, 132, 4);
+        assert!(TextureData::from_memory(&bytes, "dds", ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn rejects_dds_cube_maps_and_truncated_data() {
+        let mut cube = dds(4, 4, 0x4, b"DXT5");
+        put_u32(&mut cube, 112, 0x200);
+        cube.extend_from_slice(&[0; 16]);
+        assert!(TextureData::from_memory(&cube, "dds", ColorSpace::Srgb).is_err());
+        let mut truncated = dds(4, 4, 0x4, b"DXT5");
+        truncated.extend_from_slice(&[0; 15]);
+        assert!(TextureData::from_memory(&truncated, "dds", ColorSpace::Srgb).is_err());
+        let unknown = dds(4, 4, 0x4, b"ETC1");
+        assert!(TextureData::from_memory(&unknown, "dds", ColorSpace::Srgb).is_err());
+    }
+
+    #[test]
+    fn converts_to_half_floats() {
+        assert_eq!(f16_bits(0.0), 0x0000);
+        assert_eq!(f16_bits(-0.0), 0x8000);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert_eq!(f16_bits(0.5), 0x3800);
+        assert_eq!(f16_bits(1.0 + 1.0 / 1024.0), 0x3c01);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        // past the largest half, and above it after rounding
+        assert_eq!(f16_bits(1e6), 0x7c00);
+        assert_eq!(f16_bits(65520.0), 0x7c00);
+        assert_eq!(f16_bits(f32::INFINITY), 0x7c00);
+        assert_eq!(f16_bits(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f16_bits(f32::NAN) & 0x7fff, 0x7e00);
+        // subnormals, down to the smallest one and below
+        assert_eq!(f16_bits(2f32.powi(-14)), 0x0400);
+        assert_eq!(f16_bits(2f32.powi(-15)), 0x0200);
+        assert_eq!(f16_bits(2f32.powi(-24)), 0x0001);
+        assert_eq!(f16_bits(1e-10), 0x0000);
+    }
+
+    #[test]
+    fn maps_image_color_types_to_formats() {
+        let luma = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![5, 6]).unwrap());
+        let linear = TextureData::from_image(&luma, "luma", ColorSpace::Linear);
+        assert!(linear.format == TextureFormat::R8Unorm);
+        assert_eq!(linear.levels, vec![vec![5, 6]]);
+        let srgb = TextureData::from_image(&luma, "luma", ColorSpace::Srgb);
+        assert!(srgb.format == TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(srgb.levels, vec![vec![5, 5, 5, 255, 6, 6, 6, 255]]);
+
+        let rgb = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([1, 2, 3])));
+        let data = TextureData::from_image(&rgb, "rgb", ColorSpace::Linear);
+        assert!(data.format == TextureFormat::Rgba8Unorm);
+        assert_eq!(data.levels, vec![vec![1, 2, 3, 255]]);
+
+        let rgb16 = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(1, 1, Rgb([0, 65535, 0])));
+        let data = TextureData::from_image(&rgb16, "rgb16", ColorSpace::Srgb);
+        assert!(data.format == TextureFormat::Rgba16Float);
+        let halves = [0x0000u16, 0x3c00, 0x0000, 0x3c00];
+        let expected = halves
+            .iter()
+            .flat_map(|half| half.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(data.levels, vec![expected]);
+    }
+
+    #[test]
+    fn counts_mip_levels_down_to_1x1() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(0, 0), 1);
+        assert_eq!(mip_level_count(256, 256), 9);
+        assert_eq!(mip_level_count(256, 16), 9);
+        // non-power-of-two sizes round each level down
+        assert_eq!(mip_level_count(5, 3), 3);
+        assert_eq!(mip_level_count(640, 480), 10);
+        assert_eq!(mip_level_count(1, 100), 7);
+        assert_eq!(mip_level_count(100, 1), 7);
+    }
+
+    fn texture(format: TextureFormat, width: u32, height: u32, pixels: Vec<u8>) -> TextureData {
+        TextureData {
+            label: "texture".to_string(),
+            format,
+            width,
+            height,
+            levels: vec![pixels],
+        }
+    }
+
+    #[test]
+    fn box_filters_cpu_mipmaps() {
+        let data = texture(
+            TextureFormat::R8Unorm,
+            4,
+            2,
+            vec![0, 40, 80, 120, 20, 60, 100, 140],
+        );
+        let levels = data.with_mipmaps().unwrap().levels;
+        assert_eq!(
+            levels,
+            vec![data.levels[0].clone(), vec![30, 110], vec![70]]
+        );
+        // a single column only averages down it
+        let data = texture(TextureFormat::R8Unorm, 1, 4, vec![0, 100, 200, 40]);
+        let levels = data.with_mipmaps().unwrap().levels;
+        assert_eq!(levels[1..], [vec![50, 120], vec![85]]);
+    }
+
+    #[test]
+    fn sizes_cpu_mipmaps_of_odd_textures() {
+        let data = texture(TextureFormat::Rgba8Unorm, 5, 3, (0..60).collect());
+        let mipmapped = data.with_mipmaps().unwrap();
+        let sizes = mipmapped.levels.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![5 * 3 * 4, 2 * 4, 4]);
+        // the 2x2 texels at the top left, channel by channel
+        assert_eq!(mipmapped.levels[1][..4], [12, 13, 14, 15]);
+        let data = texture(TextureFormat::Rgba8Unorm, 5, 3, vec![0; 59]);
+        assert!(data.with_mipmaps().is_err());
+        let data = texture(TextureFormat::Rgba16Float, 2, 2, vec![0; 32]);
+        assert!(data.with_mipmaps().is_err());
+    }
+
+    #[test]
+    fn averages_srgb_cpu_mipmaps_as_light() {
+        let black_and_white = [0, 0, 0, 0, 255, 255, 255, 255].repeat(2);
+        let srgb = texture(TextureFormat::Rgba8UnormSrgb, 2, 2, black_and_white.clone());
+        // half the light of white is brighter than the middle of the sRGB range
+        assert_eq!(
+            srgb.with_mipmaps().unwrap().levels[1],
+            vec![188, 188, 188, 128]
+        );
+        let bgra = texture(TextureFormat::Bgra8UnormSrgb, 2, 2, black_and_white.clone());
+        assert_eq!(
+            bgra.with_mipmaps().unwrap().levels[1],
+            vec![188, 188, 188, 128]
+        );
+        let linear = texture(TextureFormat::Rgba8Unorm, 2, 2, black_and_white);
+        assert_eq!(linear.with_mipmaps().unwrap().levels[1], vec![128; 4]);
+    }
+
+    // a normal or zero half float
+    fn half_to_f32(bytes: &[u8]) -> f32 {
+        let bits = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let exponent = ((bits >> 10) & 0x1f) as i32;
+        let mantissa = (bits & 0x3ff) as f32 / 1024.0;
+        match exponent {
+            0 => 0.0,
+            _ => (1.0 + mantissa) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    #[test]
+    fn linearizes_16_bit_srgb_colors_but_not_alpha() {
+        let grey = Rgba([32768, 32768, 65535, 32768]);
+        let rgba16 = DynamicImage::ImageRgba16(ImageBuffer::from_pixel(1, 1, grey));
+        let channels = |color_space| {
+            let data = TextureData::from_image(&rgba16, "rgba16", color_space);
+            assert!(data.format == TextureFormat::Rgba16Float);
+            data.levels[0]
+                .chunks(2)
+                .map(half_to_f32)
+                .collect::<Vec<_>>()
+        };
+        let near = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        let srgb = channels(ColorSpace::Srgb);
+        // sRGB middle grey is about a fifth of the light of white
+        assert!(near(srgb[0], 0.2140) && near(srgb[1], 0.2140), "{:?}", srgb);
+        assert!(near(srgb[2], 1.0) && near(srgb[3], 0.5), "{:?}", srgb);
+        let linear = channels(ColorSpace::Linear);
+        assert!(linear
+            .iter()
+            .zip([0.5, 0.5, 1.0, 0.5])
+            .all(|(&a, b)| near(a, b)));
+    }
+}
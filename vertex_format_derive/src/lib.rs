@@ -0,0 +1,204 @@
+//! `#[derive(VertexFormat)]` for the vertex types of the webgpu_sotrh crate.
+//!
+//! Every field of a `#[repr(C)]` struct becomes a vertex attribute at the next
+//! shader location, with its format from `VertexAttributeType`. Offsets come from
+//! `offset_of!` (Rust 1.77), so `packed` and `align` reprs are laid out right too.
+//! A field can override location or format with
+//! `#[vertex(location = 4, format = "Unorm8x4")]`, or be left out of the layout
+//! with `#[vertex(skip)]`. Matrices like `[[f32; 4]; 4]` take one location per
+//! column, and `#[vertex(instance)]` on the struct steps the buffer per instance.
+//! Each attribute is named after its field, with `_<column>` appended for matrices,
+//! so the layout can be checked against the shader inputs of the same name.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Expr, Fields, Index,
+    Lit, Member, Meta, NestedMeta, Result, Type,
+};
+
+#[proc_macro_derive(VertexFormat, attributes(vertex))]
+pub fn derive_vertex_format(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    location: Option<u32>,
+    format: Option<syn::Ident>,
+    skip: bool,
+}
+
+fn is_repr_c(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("repr"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .any(|nested| matches!(nested, NestedMeta::Meta(Meta::Path(path)) if path.is_ident("C")))
+}
+
+fn is_instance(attrs: &[Attribute]) -> Result<bool> {
+    let mut instance = false;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("vertex")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("instance") => {
+                            instance = true
+                        }
+                        nested => return Err(Error::new(nested.span(), "expected `instance`")),
+                    }
+                }
+            }
+            meta => return Err(Error::new(meta.span(), "expected #[vertex(instance)]")),
+        }
+    }
+    Ok(instance)
+}
+
+// the column type and count of `[[T; N]; columns]`, which takes one location per column
+fn matrix_columns(ty: &Type) -> Result<Option<(&Type, u32)>> {
+    let (column, len) = match ty {
+        Type::Array(array) => match &*array.elem {
+            Type::Array(_) => (&*array.elem, &array.len),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    match len {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(int) => Ok(Some((column, int.base10_parse()?))),
+            _ => Err(Error::new(len.span(), "expected a column count")),
+        },
+        _ => Err(Error::new(
+            len.span(),
+            "the column count of a matrix must be a literal",
+        )),
+    }
+}
+
+fn field_options(attrs: &[Attribute]) -> Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("vertex")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(), "expected #[vertex(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => options.skip = true,
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("location") => {
+                    match &pair.lit {
+                        Lit::Int(int) => options.location = Some(int.base10_parse()?),
+                        lit => return Err(Error::new(lit.span(), "expected an integer")),
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("format") => {
+                    match &pair.lit {
+                        // a variant of wgpu::VertexFormat
+                        Lit::Str(name) => options.format = Some(format_ident!("{}", name.value())),
+                        lit => return Err(Error::new(lit.span(), "expected a string")),
+                    }
+                }
+                nested => {
+                    return Err(Error::new(
+                        nested.span(),
+                        "expected `skip`, `location = ..` or `format = \"..\"`",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "VertexFormat can't be derived for generic structs",
+        ));
+    }
+    if !is_repr_c(&input.attrs) {
+        return Err(Error::new(
+            name.span(),
+            "VertexFormat needs #[repr(C)] to keep the fields in order",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect::<Vec<_>>(),
+            Fields::Unnamed(fields) => fields.unnamed.iter().collect(),
+            Fields::Unit => Vec::new(),
+        },
+        _ => {
+            return Err(Error::new(
+                name.span(),
+                "VertexFormat can only be derived for structs",
+            ))
+        }
+    };
+    let mut attributes = Vec::new();
+    let mut names = Vec::new();
+    let mut next_location = 0;
+    for (index, field) in fields.iter().enumerate() {
+        let options = field_options(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+        let location = options.location.unwrap_or(next_location);
+        let matrix = matrix_columns(&field.ty)?;
+        let (ty, columns) = matrix.unwrap_or((&field.ty, 1));
+        next_location = location + columns;
+        let format = match options.format {
+            Some(format) => quote!(wgpu::VertexFormat::#format),
+            None => quote!(<#ty as crate::buffers::VertexAttributeType>::FORMAT),
+        };
+        let (member, field_name) = match &field.ident {
+            Some(ident) => (Member::Named(ident.clone()), ident.to_string()),
+            None => (Member::Unnamed(Index::from(index)), index.to_string()),
+        };
+        for column in 0..columns {
+            names.push(match matrix {
+                Some(_) => format!("{}_{}", field_name, column),
+                None => field_name.clone(),
+            });
+            let location = location + column;
+            let column = column as usize;
+            attributes.push(quote! {
+                wgpu::VertexAttribute {
+                    offset: (::core::mem::offset_of!(#name, #member)
+                        + #column * ::core::mem::size_of::<#ty>())
+                        as wgpu::BufferAddress,
+                    format: #format,
+                    shader_location: #location,
+                }
+            });
+        }
+    }
+    let step_mode = match is_instance(&input.attrs)? {
+        true => quote! {
+            const STEP_MODE: wgpu::InputStepMode = wgpu::InputStepMode::Instance;
+        },
+        false => quote!(),
+    };
+    Ok(quote! {
+        impl crate::buffers::VertexFormat for #name {
+            #step_mode
+
+            const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &[#(#attributes),*];
+
+            const ATTRIBUTE_NAMES: &'static [&'static str] = &[#(#names),*];
+        }
+    })
+}